@@ -1,9 +1,13 @@
+use std::env;
 use std::error::Error;
 use cc::Build;
 
 fn main() -> Result<(), Box<dyn Error>> {
     println!("cargo:rerun-if-changed=src/asm.s");
-    Build::new().file("src/asm.s").compile("asm");
+    // The unit tests are built for the host, which cannot assemble the context switch
+    if env::var("TARGET")?.starts_with("thumb") {
+        Build::new().file("src/asm.s").compile("asm");
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -27,8 +27,17 @@ pub struct SimpleAllocator {
     head: ListNode,
 }
 
-fn align_addr(addr: usize, align: usize) -> usize {
-    (addr + align - 1) / align * align
+fn align_addr(addr: usize, align: usize) -> Option<usize> {
+    Some(addr.checked_add(align - 1)? / align * align)
+}
+
+// Start of a block of `size` bytes aligned to `align` inside [start_addr, end_addr)
+fn fit(start_addr: usize, end_addr: usize, size: usize, align: usize) -> Option<usize> {
+    let aligned_addr = align_addr(start_addr, align)?;
+    if aligned_addr.checked_add(size)? > end_addr {
+        return None;
+    }
+    Some(aligned_addr)
 }
 
 impl SimpleAllocator {
@@ -40,12 +49,12 @@ impl SimpleAllocator {
 
     pub unsafe fn add_new_node(&mut self, start_addr: usize, size: usize) {
         let end_addr = start_addr + size;
-        let aligned_addr = align_addr(start_addr, align_of::<ListNode>());
+        let aligned_addr = match fit(start_addr, end_addr, size_of::<ListNode>(), align_of::<ListNode>()) {
+            Some(aligned_addr) => aligned_addr,
+            None => return,
+        };
 
         let size = end_addr - aligned_addr;
-        if size < size_of::<ListNode>() {
-            return;
-        }
 
         let new_area_ptr = aligned_addr as *mut ListNode;
         (*new_area_ptr).size = size;
@@ -62,12 +71,9 @@ impl SimpleAllocator {
         // Find empty list from head
         while let Some(ref mut node) = current.next {
             let start_addr = node.start_addr();
-            let aligned_addr = align_addr(start_addr, align);
             let end_addr = node.end_addr();
-            let node_size = end_addr - aligned_addr;
-            if node_size < size {
-                current = current.next.as_mut().unwrap();
-            } else {
+            // A node smaller than the alignment can end before the aligned address
+            if let Some(aligned_addr) = fit(start_addr, end_addr, size, align) {
                 let next = current.next.take();
                 let result = aligned_addr as *mut u8;
                 current.next = next.unwrap().next.take();
@@ -77,6 +83,7 @@ impl SimpleAllocator {
 
                 return result;
             }
+            current = current.next.as_mut().unwrap();
         }
         return core::ptr::null_mut();
     }
@@ -86,6 +93,25 @@ impl SimpleAllocator {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::fit;
+
+    #[test]
+    fn test_fit_small_node_large_align() {
+        // A 64 byte node cannot hold a block aligned to 1024 that starts past it
+        assert_eq!(None, fit(0x2000_0040, 0x2000_0080, 32, 1024));
+        assert_eq!(Some(0x2000_0400), fit(0x2000_0040, 0x2000_0800, 1024, 1024));
+        assert_eq!(None, fit(0x2000_0040, 0x2000_07FF, 1024, 1024));
+    }
+
+    #[test]
+    fn test_fit_end_of_memory() {
+        assert_eq!(None, fit(usize::MAX - 8, usize::MAX, 4, 16));
+        assert_eq!(Some(0x100), fit(0x100, 0x110, 16, 8));
+    }
+}
+
 unsafe impl GlobalAlloc for Mutex<SimpleAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         self.lock().alloc(layout)
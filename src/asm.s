@@ -1,17 +1,93 @@
 .cpu cortex-m4
+.fpu fpv4-sp-d16
 .thumb
 
+.section .bss
+.align 2
+/* EXC_RETURN of the process that PendSV switches to and from */
+process_exc_return:
+    .word 0
+
 .section .text
 .syntax unified
 .global asm_execute_process
+.global PendSV
+
+.equ ICSR_ADDR, 0xE000ED04
+.equ ICSR_PENDSVSET, 0x10000000
+.equ ICSR_PENDSVCLR, 0x08000000
+.equ CONTROL_FPCA, 0x4
+/* EXC_RETURN bit 4 is clear when the process has an active FP context */
+.equ EXC_RETURN_STD_FRAME, 0x10
+.equ EXC_RETURN_THREAD_MSP, 0xFFFFFFF9
 
+/* r0: stack pointer of the process, r1: SavedRegisters of the process */
 asm_execute_process:
     push {r4, r5, r6, r7, lr}
     push {r8, r9, r10, r11}
+    vpush {s16-s31}
+    ldr r2, [r1, #32]
+    ldr r3, =process_exc_return
+    str r2, [r3]
+    tst r2, #EXC_RETURN_STD_FRAME
+    bne 1f
+    add r3, r1, #36
+    vldmia r3, {s16-s31}
+1:
+    /* Drop the kernel's FP context so PendSV always sees a standard frame */
+    mrs r3, CONTROL
+    bic r3, r3, #CONTROL_FPCA
+    msr CONTROL, r3
+    isb
     ldmia r1, {r4-r11}
     msr psp, r0
-    svc 0
+    /* PendSV switches to the process and back when the process traps */
+    ldr r2, =ICSR_ADDR
+    ldr r3, =ICSR_PENDSVSET
+    str r3, [r2]
+    dsb
+    isb
     stmia r1, {r4-r11}
+    ldr r3, =process_exc_return
+    ldr r2, [r3]
+    str r2, [r1, #32]
+    tst r2, #EXC_RETURN_STD_FRAME
+    bne 2f
+    /* Also triggers the lazy stacking of s0-s15 into the process's frame */
+    add r3, r1, #36
+    vstmia r3, {s16-s31}
+2:
     mrs r0, psp
+    vpop {s16-s31}
     pop {r8, r9, r10, r11}
     pop {r4, r5, r6, r7, pc}
+
+.thumb_func
+PendSV:
+    /* EXC_RETURN bit 2 is set when we came from a process on PSP */
+    tst lr, #4
+    bne 1f
+
+    /* kernel -> process */
+    mov r0, #1
+    msr CONTROL, r0
+    isb
+    ldr r0, =process_exc_return
+    ldr lr, [r0]
+    bx lr
+
+    /* process -> kernel */
+1:
+    ldr r0, =process_exc_return
+    str lr, [r0]
+    mov r0, #0
+    msr CONTROL, r0
+    isb
+    /* SysTick may have pended PendSV again while CONTROL still said the
+       process was running. Drop that, or the kernel would switch straight
+       back to the process before handling why it stopped */
+    ldr r0, =ICSR_ADDR
+    ldr r1, =ICSR_PENDSVCLR
+    str r1, [r0]
+    ldr lr, =EXC_RETURN_THREAD_MSP
+    bx lr
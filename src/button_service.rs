@@ -0,0 +1,125 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use userland::abi::{ButtonEvent, ButtonEventKind, BUTTON_EVENT_FLAGS, NUM_BUTTONS};
+use crate::button::{Button1, Button2, Button3};
+use crate::port::{Port, PortC};
+use crate::scheduler;
+use crate::sync;
+
+// A new level must hold this long to count
+const DEBOUNCE_TICKS: u32 = 20;
+const LONG_PRESS_TICKS: u32 = 1000;
+
+// Debounced state of each button
+static PRESSED: [AtomicBool; NUM_BUTTONS] = [AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false)];
+
+// Events from SysTick to the kernel. SysTick only moves TAIL and the kernel only moves HEAD.
+const QUEUE_LEN: usize = 16;
+// Only used to initialize the array below
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicU32 = AtomicU32::new(0);
+static EVENTS: [AtomicU32; QUEUE_LEN] = [EMPTY; QUEUE_LEN];
+static HEAD: AtomicUsize = AtomicUsize::new(0);
+static TAIL: AtomicUsize = AtomicUsize::new(0);
+
+struct Debouncer {
+    pressed: bool,
+    // Ticks the raw level has differed from `pressed`
+    changing_ticks: u32,
+    // Ticks since the last press
+    held_ticks: u32,
+}
+
+impl Debouncer {
+    const fn new() -> Self {
+        Debouncer { pressed: false, changing_ticks: 0, held_ticks: 0 }
+    }
+
+    fn update(&mut self, raw: bool) -> Option<ButtonEventKind> {
+        if raw == self.pressed {
+            self.changing_ticks = 0;
+            if self.pressed {
+                self.held_ticks = self.held_ticks.saturating_add(1);
+                if self.held_ticks == LONG_PRESS_TICKS {
+                    return Some(ButtonEventKind::LongPress);
+                }
+            }
+            return None;
+        }
+        self.changing_ticks += 1;
+        if self.changing_ticks < DEBOUNCE_TICKS {
+            return None;
+        }
+        self.pressed = raw;
+        self.changing_ticks = 0;
+        self.held_ticks = 0;
+        if raw {
+            Some(ButtonEventKind::Press)
+        } else {
+            Some(ButtonEventKind::Release)
+        }
+    }
+}
+
+// Only touched from the SysTick handler
+static mut DEBOUNCERS: [Debouncer; NUM_BUTTONS] = [Debouncer::new(), Debouncer::new(), Debouncer::new()];
+
+fn read_buttons() -> [bool; NUM_BUTTONS] {
+    let portc = Port::<PortC>::new();
+    [
+        Button1::new(&portc.pin26).is_pushed(),
+        Button2::new(&portc.pin27).is_pushed(),
+        Button3::new(&portc.pin28).is_pushed(),
+    ]
+}
+
+pub fn init() {
+    let portc = Port::<PortC>::new();
+    Button1::new(&portc.pin26).init();
+    Button2::new(&portc.pin27).init();
+    Button3::new(&portc.pin28).init();
+}
+
+/// Sample and debounce the buttons. Called from the SysTick handler.
+pub fn tick() {
+    let raw = read_buttons();
+    let debouncers = unsafe { &mut DEBOUNCERS };
+    for (button, debouncer) in debouncers.iter_mut().enumerate() {
+        if let Some(kind) = debouncer.update(raw[button]) {
+            PRESSED[button].store(debouncer.pressed, Ordering::Relaxed);
+            push(ButtonEvent { button: button as u32, kind });
+            if kind == ButtonEventKind::Press {
+                sync::set_flags_from_isr(BUTTON_EVENT_FLAGS as usize, 1 << button);
+            }
+        }
+    }
+    if HEAD.load(Ordering::Acquire) != TAIL.load(Ordering::Relaxed) {
+        scheduler::request_switch(scheduler::SWITCH_BUTTON);
+    }
+}
+
+// Drops the event when the kernel has fallen behind
+fn push(event: ButtonEvent) {
+    let tail = TAIL.load(Ordering::Relaxed);
+    let next = (tail + 1) % QUEUE_LEN;
+    if next == HEAD.load(Ordering::Acquire) {
+        return;
+    }
+    EVENTS[tail].store(event.to_u32(), Ordering::Relaxed);
+    TAIL.store(next, Ordering::Release);
+}
+
+/// Take the oldest event that SysTick has recorded.
+pub fn pop() -> Option<ButtonEvent> {
+    let head = HEAD.load(Ordering::Relaxed);
+    if head == TAIL.load(Ordering::Acquire) {
+        return None;
+    }
+    let event = EVENTS[head].load(Ordering::Relaxed);
+    HEAD.store((head + 1) % QUEUE_LEN, Ordering::Release);
+    ButtonEvent::from_u32(event)
+}
+
+/// Debounced state of a button.
+pub fn is_pressed(button: usize) -> bool {
+    PRESSED[button].load(Ordering::Relaxed)
+}
@@ -0,0 +1,25 @@
+use cortex_m_semihosting::hio::{self, HStdout};
+
+/// Where the output of the write syscall ends up.
+pub trait Console {
+    fn write(&mut self, bytes: &[u8]);
+}
+
+/// Prints on the host through semihosting.
+pub struct Semihosting {
+    stdout: Option<HStdout>,
+}
+
+impl Semihosting {
+    pub fn new() -> Self {
+        Semihosting { stdout: hio::hstdout().ok() }
+    }
+}
+
+impl Console for Semihosting {
+    fn write(&mut self, bytes: &[u8]) {
+        if let Some(stdout) = self.stdout.as_mut() {
+            let _ = stdout.write_all(bytes);
+        }
+    }
+}
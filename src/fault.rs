@@ -0,0 +1,226 @@
+use cortex_m_semihosting::hprintln;
+use core::arch::asm;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::process::ContextFrame;
+use crate::scheduler;
+
+const SHCSR_ADDR: usize = 0xE000_ED24;
+const CFSR_ADDR: usize = 0xE000_ED28;
+const HFSR_ADDR: usize = 0xE000_ED2C;
+const MMFAR_ADDR: usize = 0xE000_ED34;
+const BFAR_ADDR: usize = 0xE000_ED38;
+
+const SHCSR_MEMFAULTENA: u32 = 1 << 16;
+const SHCSR_BUSFAULTENA: u32 = 1 << 17;
+const SHCSR_USGFAULTENA: u32 = 1 << 18;
+
+// EXC_RETURN bits 3 and 2: the fault interrupted thread mode running on PSP
+const EXC_RETURN_THREAD_PSP_MASK: u32 = 0b1100;
+
+const CFSR_MSTKERR: u32 = 1 << 4;
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_STKERR: u32 = 1 << 12;
+const CFSR_BFARVALID: u32 = 1 << 15;
+// The exception frame could not be pushed, so the stack holds no registers
+const CFSR_STACKING_ERRORS: u32 = CFSR_MSTKERR | CFSR_STKERR;
+
+const CFSR_CAUSES: [(u32, &str); 19] = [
+    // MemManage Status Register
+    (1 << 0, "IACCVIOL: instruction fetch from a location without execute permission"),
+    (1 << 1, "DACCVIOL: load or store to a location without permission"),
+    (1 << 3, "MUNSTKERR: MPU violation while unstacking on exception return"),
+    (CFSR_MSTKERR, "MSTKERR: MPU violation while stacking on exception entry"),
+    (1 << 5, "MLSPERR: MPU violation during lazy FP state preservation"),
+    (CFSR_MMARVALID, "MMARVALID: MMFAR holds the faulting address"),
+    // BusFault Status Register
+    (1 << 8, "IBUSERR: bus error on instruction fetch"),
+    (1 << 9, "PRECISERR: precise data bus error, the stacked pc is the faulting instruction"),
+    (1 << 10, "IMPRECISERR: imprecise data bus error, the stacked pc is after the faulting instruction"),
+    (1 << 11, "UNSTKERR: bus error while unstacking on exception return"),
+    (CFSR_STKERR, "STKERR: bus error while stacking on exception entry"),
+    (1 << 13, "LSPERR: bus error during lazy FP state preservation"),
+    (CFSR_BFARVALID, "BFARVALID: BFAR holds the faulting address"),
+    // UsageFault Status Register
+    (1 << 16, "UNDEFINSTR: undefined instruction"),
+    (1 << 17, "INVSTATE: invalid EPSR state, e.g. a branch to an ARM-state address"),
+    (1 << 18, "INVPC: invalid EXC_RETURN or pc load on exception return"),
+    (1 << 19, "NOCP: coprocessor access while the FPU is disabled"),
+    (1 << 24, "UNALIGNED: unaligned access while unaligned trapping is enabled"),
+    (1 << 25, "DIVBYZERO: integer division by zero"),
+];
+
+const HFSR_CAUSES: [(u32, &str); 3] = [
+    (1 << 1, "VECTTBL: bus error on a vector table read"),
+    (1 << 30, "FORCED: a configurable fault escalated to HardFault"),
+    (1 << 31, "DEBUGEVT: debug event while the debugger is disabled"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum FaultKind {
+    HardFault = 1,
+    MemManage = 2,
+    BusFault = 3,
+    UsageFault = 4,
+}
+
+impl FaultKind {
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            1 => Some(FaultKind::HardFault),
+            2 => Some(FaultKind::MemManage),
+            3 => Some(FaultKind::BusFault),
+            4 => Some(FaultKind::UsageFault),
+            _ => None,
+        }
+    }
+}
+
+/// The fault status registers, captured by a fault handler.
+#[derive(Clone, Copy, Debug)]
+pub struct Fault {
+    pub kind: FaultKind,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+}
+
+impl Fault {
+    fn read(kind: FaultKind) -> Self {
+        unsafe {
+            Fault {
+                kind,
+                cfsr: read_volatile(CFSR_ADDR as *const u32),
+                hfsr: read_volatile(HFSR_ADDR as *const u32),
+                mmfar: read_volatile(MMFAR_ADDR as *const u32),
+                bfar: read_volatile(BFAR_ADDR as *const u32),
+            }
+        }
+    }
+
+    /// Address of the faulting data access, when the MPU recorded one.
+    pub fn mem_manage_address(&self) -> Option<u32> {
+        if self.cfsr & CFSR_MMARVALID != 0 {
+            Some(self.mmfar)
+        } else {
+            None
+        }
+    }
+
+    /// Address of the faulting bus access, when the bus recorded one.
+    pub fn bus_fault_address(&self) -> Option<u32> {
+        if self.cfsr & CFSR_BFARVALID != 0 {
+            Some(self.bfar)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the registers of the faulting context were pushed to its stack.
+    pub fn has_stacked_frame(&self) -> bool {
+        self.cfsr & CFSR_STACKING_ERRORS == 0
+    }
+
+    pub fn print(&self) {
+        hprintln!("{:?}", self.kind).unwrap();
+        hprintln!("  CFSR:{:08X}", self.cfsr).unwrap();
+        for (bit, cause) in CFSR_CAUSES.iter() {
+            if self.cfsr & bit != 0 {
+                hprintln!("    {}", cause).unwrap();
+            }
+        }
+        hprintln!("  HFSR:{:08X}", self.hfsr).unwrap();
+        for (bit, cause) in HFSR_CAUSES.iter() {
+            if self.hfsr & bit != 0 {
+                hprintln!("    {}", cause).unwrap();
+            }
+        }
+        if let Some(address) = self.mem_manage_address() {
+            hprintln!("  MMFAR:{:08X}", address).unwrap();
+        }
+        if let Some(address) = self.bus_fault_address() {
+            hprintln!("  BFAR:{:08X}", address).unwrap();
+        }
+    }
+}
+
+/// Dump the registers that the exception entry pushed.
+pub fn print_frame(frame: &ContextFrame) {
+    hprintln!("  r0:{:08X} r1:{:08X} r2:{:08X} r3:{:08X}", frame.r0, frame.r1, frame.r2, frame.r3).unwrap();
+    hprintln!("  r12:{:08X} lr:{:08X} pc:{:08X} xpsr:{:08X}", frame.r12, frame.lr, frame.return_addr, frame.xpsr).unwrap();
+}
+
+// Handed from the fault handler to the kernel. FAULT_KIND is 0 when there is none.
+static FAULT_KIND: AtomicU32 = AtomicU32::new(0);
+static FAULT_CFSR: AtomicU32 = AtomicU32::new(0);
+static FAULT_HFSR: AtomicU32 = AtomicU32::new(0);
+static FAULT_MMFAR: AtomicU32 = AtomicU32::new(0);
+static FAULT_BFAR: AtomicU32 = AtomicU32::new(0);
+
+/// Give MemManage, BusFault and UsageFault their own handlers
+/// instead of escalating them to HardFault.
+pub fn init() {
+    unsafe {
+        let shcsr = read_volatile(SHCSR_ADDR as *const u32);
+        write_volatile(SHCSR_ADDR as *mut u32, shcsr | SHCSR_MEMFAULTENA | SHCSR_BUSFAULTENA | SHCSR_USGFAULTENA);
+        asm!("dsb", "isb");
+    }
+}
+
+/// The fault that the last switch to the kernel was requested for.
+pub fn take() -> Option<Fault> {
+    let kind = FaultKind::from_u32(FAULT_KIND.swap(0, Ordering::Relaxed))?;
+    Some(Fault {
+        kind,
+        cfsr: FAULT_CFSR.load(Ordering::Relaxed),
+        hfsr: FAULT_HFSR.load(Ordering::Relaxed),
+        mmfar: FAULT_MMFAR.load(Ordering::Relaxed),
+        bfar: FAULT_BFAR.load(Ordering::Relaxed),
+    })
+}
+
+fn handle(exc_return: u32, sp: u32, kind: FaultKind) {
+    let fault = Fault::read(kind);
+
+    if exc_return & EXC_RETURN_THREAD_PSP_MASK != EXC_RETURN_THREAD_PSP_MASK {
+        // The kernel itself faulted. There is nothing left to run safely.
+        hprintln!("Kernel fault").unwrap();
+        fault.print();
+        hprintln!("  SHCSR:{:08X}", unsafe { read_volatile(SHCSR_ADDR as *const u32) }).unwrap();
+        if fault.has_stacked_frame() {
+            print_frame(unsafe { &*(sp as *const ContextFrame) });
+        }
+        loop {}
+    }
+
+    FAULT_CFSR.store(fault.cfsr, Ordering::Relaxed);
+    FAULT_HFSR.store(fault.hfsr, Ordering::Relaxed);
+    FAULT_MMFAR.store(fault.mmfar, Ordering::Relaxed);
+    FAULT_BFAR.store(fault.bfar, Ordering::Relaxed);
+    FAULT_KIND.store(kind as u32, Ordering::Relaxed);
+    unsafe {
+        // The status bits are sticky, so clear them for the next fault
+        write_volatile(CFSR_ADDR as *mut u32, fault.cfsr);
+        write_volatile(HFSR_ADDR as *mut u32, fault.hfsr);
+    }
+    // PendSV runs right after we return, before the faulting instruction is retried
+    scheduler::request_switch(scheduler::SWITCH_FAULT);
+}
+
+pub extern "C" fn hard_fault(exc_return: u32, sp: u32) {
+    handle(exc_return, sp, FaultKind::HardFault);
+}
+
+pub extern "C" fn mem_manage(exc_return: u32, sp: u32) {
+    handle(exc_return, sp, FaultKind::MemManage);
+}
+
+pub extern "C" fn bus_fault(exc_return: u32, sp: u32) {
+    handle(exc_return, sp, FaultKind::BusFault);
+}
+
+pub extern "C" fn usage_fault(exc_return: u32, sp: u32) {
+    handle(exc_return, sp, FaultKind::UsageFault);
+}
@@ -0,0 +1,22 @@
+use cortex_m_semihosting::hprintln;
+use core::arch::asm;
+use core::ptr::{read_volatile, write_volatile};
+
+const CPACR_ADDR: usize = 0xE000_ED88;
+const FPCCR_ADDR: usize = 0xE000_EF34;
+
+const CPACR_CP10_CP11_FULL: u32 = 0xF << 20;
+const FPCCR_ASPEN: u32 = 1 << 31;
+const FPCCR_LSPEN: u32 = 1 << 30;
+
+pub fn init() {
+    unsafe {
+        let cpacr = read_volatile(CPACR_ADDR as *const u32);
+        write_volatile(CPACR_ADDR as *mut u32, cpacr | CPACR_CP10_CP11_FULL);
+        // Stack s0-s15 only when a handler actually touches the FPU
+        let fpccr = read_volatile(FPCCR_ADDR as *const u32);
+        write_volatile(FPCCR_ADDR as *mut u32, fpccr | FPCCR_ASPEN | FPCCR_LSPEN);
+        asm!("dsb", "isb");
+    }
+    hprintln!("FPU init").unwrap();
+}
@@ -0,0 +1,81 @@
+use userland::abi::{PinMode, SyscallError};
+use crate::port::{DynPin, Pin, PortId, Pull, NUM_PORTS, PINS_PER_PORT};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PinOwner {
+    Kernel,
+    Process(usize),
+}
+
+/// Which pins are claimed, and by whom.
+pub struct PinTable {
+    owners: [[Option<PinOwner>; PINS_PER_PORT]; NUM_PORTS],
+}
+
+impl PinTable {
+    pub const fn new() -> Self {
+        PinTable { owners: [[None; PINS_PER_PORT]; NUM_PORTS] }
+    }
+
+    /// Keep a pin that a kernel driver uses away from processes.
+    pub fn reserve<P: PortId, const N: usize>(&mut self, _pin: &Pin<P, N>) {
+        self.owners[P::INDEX][N] = Some(PinOwner::Kernel);
+    }
+
+    pub fn claim(&mut self, port: usize, n: usize, owner: PinOwner) -> Result<DynPin, SyscallError> {
+        let pin = DynPin::new(port, n).ok_or(SyscallError::InvalidArgument)?;
+        match self.owners[port][n] {
+            Some(current) if current != owner => Err(SyscallError::Busy),
+            _ => {
+                self.owners[port][n] = Some(owner);
+                Ok(pin)
+            },
+        }
+    }
+
+    pub fn release(&mut self, port: usize, n: usize, owner: PinOwner) -> Result<(), SyscallError> {
+        self.get(port, n, owner)?;
+        self.owners[port][n] = None;
+        Ok(())
+    }
+
+    /// Release every pin of `owner`, when its process terminates.
+    pub fn release_all(&mut self, owner: PinOwner) {
+        for slot in self.owners.iter_mut().flatten().filter(|slot| **slot == Some(owner)) {
+            *slot = None;
+        }
+    }
+
+    /// The pin, if `owner` has claimed it.
+    pub fn get(&self, port: usize, n: usize, owner: PinOwner) -> Result<DynPin, SyscallError> {
+        let pin = DynPin::new(port, n).ok_or(SyscallError::InvalidArgument)?;
+        if self.owners[port][n] == Some(owner) {
+            Ok(pin)
+        } else {
+            Err(SyscallError::PermissionDenied)
+        }
+    }
+}
+
+pub fn configure(pin: &DynPin, mode: PinMode) {
+    match mode {
+        PinMode::Output => {
+            // Also drops the pull of an earlier input mode. The input buffer
+            // stays on so that reads return the driven level.
+            pin.enable_input(Pull::None);
+            pin.set_dir();
+        },
+        PinMode::Input => {
+            pin.clear_dir();
+            pin.enable_input(Pull::None);
+        },
+        PinMode::InputPullUp => {
+            pin.clear_dir();
+            pin.enable_input(Pull::Up);
+        },
+        PinMode::InputPullDown => {
+            pin.clear_dir();
+            pin.enable_input(Pull::Down);
+        },
+    }
+}
@@ -0,0 +1,13 @@
+use crate::mpu::Region;
+
+/// Access that `owner` gives `grantee` to a buffer on its stack.
+/// The region stays in the regions of the grantee until it is revoked.
+#[derive(Clone, Copy, Debug)]
+pub struct Grant {
+    pub owner: usize,
+    pub grantee: usize,
+    pub region: Region,
+    // Set when the grantee stops. The handle stays taken until the owner
+    // revokes it, so that a stale handle cannot revoke a newer grant.
+    pub revoked: bool,
+}
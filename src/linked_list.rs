@@ -1,5 +1,3 @@
-#![cfg_attr(test, no_std)]
-
 use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 use core::marker::PhantomData;
@@ -50,6 +48,7 @@ impl<'a, T> LinkedList<'a, T> {
     }
 
     pub fn push(&mut self, item: &'a mut ListItem<'a, T>) {
+        item.next = None;
         let ptr = unsafe {
             NonNull::new_unchecked(item as *mut ListItem<T>)
         };
@@ -64,14 +63,65 @@ impl<'a, T> LinkedList<'a, T> {
         }
     }
 
+    pub fn push_front(&mut self, item: &'a mut ListItem<'a, T>) {
+        item.next = self.head;
+        let ptr = unsafe {
+            NonNull::new_unchecked(item as *mut ListItem<T>)
+        };
+
+        if self.head.is_none() {
+            self.last = Some(ptr);
+        }
+        self.head = Some(ptr);
+    }
+
+    /// Link `item` in front of the first item whose value matches `f`, or last when none does.
+    pub fn insert_before<F: FnMut(&T) -> bool>(&mut self, item: &'a mut ListItem<'a, T>, mut f: F) {
+        let mut prev: Option<NonNull<ListItem<'a, T>>> = None;
+        let mut current = self.head;
+
+        while let Some(ptr) = current {
+            let next = unsafe { &*ptr.as_ptr() };
+            if f(&next.value) {
+                break;
+            }
+            prev = current;
+            current = next.next;
+        }
+        match prev {
+            None => self.push_front(item),
+            Some(_) if current.is_none() => self.push(item),
+            Some(mut prev) => {
+                item.next = current;
+                unsafe {
+                    prev.as_mut().next = Some(NonNull::new_unchecked(item as *mut ListItem<T>));
+                }
+            },
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.head.is_none()
     }
 
-    pub fn head_mut(&mut self) -> Option<&mut T> {
+    pub fn head(&self) -> Option<&T> {
         self.head.map(|ptr| unsafe {
-            &mut *ptr.as_ptr()
-        }.deref_mut())
+            &*ptr.as_ptr()
+        }.deref())
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'a, T> {
+        Iter {
+            next: self.head,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, 'a, T> {
+        IterMut {
+            next: self.head,
+            marker: PhantomData,
+        }
     }
 
     pub fn pop(&mut self) -> Option<&'a mut ListItem<'a, T>> {
@@ -89,12 +139,68 @@ impl<'a, T> LinkedList<'a, T> {
         result.map(|ptr| unsafe { &mut *ptr.as_ptr() })
     }
 
+    /// Unlink and return the first item whose value matches `f`.
+    pub fn remove_first<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> Option<&'a mut ListItem<'a, T>> {
+        let mut prev: Option<NonNull<ListItem<'a, T>>> = None;
+        let mut current = self.head;
+
+        while let Some(ptr) = current {
+            let item = unsafe { &mut *ptr.as_ptr() };
+            if f(&item.value) {
+                let next = item.next.take();
+                match prev {
+                    None => self.head = next,
+                    Some(mut prev) => unsafe { prev.as_mut().next = next },
+                }
+                if next.is_none() {
+                    self.last = prev;
+                }
+                return Some(item);
+            }
+            prev = current;
+            current = item.next;
+        }
+        None
+    }
+}
+
+pub struct Iter<'l, 'a, T> {
+    next: Option<NonNull<ListItem<'a, T>>>,
+    marker: PhantomData<&'l ListItem<'a, T>>,
+}
+
+impl<'l, 'a, T> Iterator for Iter<'l, 'a, T> {
+    type Item = &'l T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next.map(|ptr| {
+            let item = unsafe { &*ptr.as_ptr() };
+            self.next = item.next;
+            &item.value
+        })
+    }
+}
+
+pub struct IterMut<'l, 'a, T> {
+    next: Option<NonNull<ListItem<'a, T>>>,
+    marker: PhantomData<&'l mut ListItem<'a, T>>,
+}
+
+impl<'l, 'a, T> Iterator for IterMut<'l, 'a, T> {
+    type Item = &'l mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next.map(|ptr| {
+            let item = unsafe { &mut *ptr.as_ptr() };
+            self.next = item.next;
+            &mut item.value
+        })
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use ListItem;
-    use LinkedList;
+    use super::{LinkedList, ListItem};
 
     #[test]
     fn test_list() {
@@ -107,11 +213,11 @@ mod test {
         list.push(&mut item2);
         list.push(&mut item3);
 
-        assert_eq!(Some(&mut 1), list.head_mut());
+        assert_eq!(Some(&1), list.head());
         let result1: &u32 = list.pop().unwrap();
-        assert_eq!(Some(&mut 2), list.head_mut());
+        assert_eq!(Some(&2), list.head());
         let result2: &u32 = list.pop().unwrap();
-        assert_eq!(Some(&mut 3), list.head_mut());
+        assert_eq!(Some(&3), list.head());
         let result3: &u32 = list.pop().unwrap();
         assert_eq!(1, *result1);
         assert_eq!(2, *result2);
@@ -131,4 +237,85 @@ mod test {
 
         assert!(list.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_remove_first() {
+        let mut item1 = ListItem::new(1);
+        let mut item2 = ListItem::new(2);
+        let mut item3 = ListItem::new(3);
+        let mut list = LinkedList::new();
+
+        list.push(&mut item1);
+        list.push(&mut item2);
+        list.push(&mut item3);
+
+        let result2: &u32 = list.remove_first(|v| *v == 2).unwrap();
+        assert_eq!(2, *result2);
+        assert!(list.remove_first(|v| *v == 2).is_none());
+
+        let result3: &u32 = list.remove_first(|v| *v == 3).unwrap();
+        assert_eq!(3, *result3);
+
+        let mut item4 = ListItem::new(4);
+        list.push(&mut item4);
+
+        let values: [u32; 2] = [1, 4];
+        assert!(list.iter().eq(values.iter()));
+
+        let result1: &u32 = list.pop().unwrap();
+        let result4: &u32 = list.pop().unwrap();
+        assert_eq!(1, *result1);
+        assert_eq!(4, *result4);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn test_insert_before() {
+        let mut item1 = ListItem::new(1);
+        let mut item2 = ListItem::new(2);
+        let mut item3 = ListItem::new(3);
+        let mut item4 = ListItem::new(4);
+        let mut list = LinkedList::new();
+
+        list.insert_before(&mut item3, |v| *v > 3);
+        list.insert_before(&mut item1, |v| *v > 1);
+        list.insert_before(&mut item4, |v| *v > 4);
+        list.insert_before(&mut item2, |v| *v > 2);
+        assert_eq!(Some(&1), list.head());
+
+        let values: [u32; 4] = [1, 2, 3, 4];
+        assert!(list.iter().eq(values.iter()));
+
+        let mut item5 = ListItem::new(5);
+        list.push(&mut item5);
+        let result1: &u32 = list.pop().unwrap();
+        assert_eq!(1, *result1);
+        let result5: &u32 = list.remove_first(|v| *v == 5).unwrap();
+        assert_eq!(5, *result5);
+        assert!(list.iter().eq(values[1..].iter()));
+    }
+
+    #[test]
+    fn test_push_front() {
+        let mut item1 = ListItem::new(1);
+        let mut item2 = ListItem::new(2);
+        let mut item3 = ListItem::new(3);
+        let mut list = LinkedList::new();
+
+        list.push_front(&mut item2);
+        list.push(&mut item3);
+        list.push_front(&mut item1);
+
+        for value in list.iter_mut() {
+            *value *= 10;
+        }
+
+        let result1: &u32 = list.pop().unwrap();
+        let result2: &u32 = list.pop().unwrap();
+        let result3: &u32 = list.pop().unwrap();
+        assert_eq!(10, *result1);
+        assert_eq!(20, *result2);
+        assert_eq!(30, *result3);
+        assert!(list.is_empty());
+    }
+}
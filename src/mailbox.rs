@@ -0,0 +1,87 @@
+use userland::abi::MESSAGE_SIZE;
+
+/// Messages a process can have waiting before senders block.
+pub const MAILBOX_CAPACITY: usize = 4;
+
+#[derive(Clone, Copy)]
+pub struct Message {
+    pub sender: usize,
+    pub data: [u8; MESSAGE_SIZE],
+}
+
+/// Messages sent to a process that it has not received yet, oldest first.
+pub struct Mailbox {
+    messages: [Message; MAILBOX_CAPACITY],
+    len: usize,
+}
+
+impl Mailbox {
+    pub fn new() -> Self {
+        Mailbox {
+            messages: [Message { sender: 0, data: [0; MESSAGE_SIZE] }; MAILBOX_CAPACITY],
+            len: 0,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == MAILBOX_CAPACITY
+    }
+
+    /// Returns false when the mailbox is full.
+    pub fn push(&mut self, message: Message) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.messages[self.len] = message;
+        self.len += 1;
+        true
+    }
+
+    /// Remove the oldest message, or the oldest from `sender` if one is given.
+    pub fn take(&mut self, sender: Option<usize>) -> Option<Message> {
+        let i = self.messages[..self.len].iter().position(|m| sender.map_or(true, |s| m.sender == s))?;
+        let message = self.messages[i];
+        self.messages.copy_within(i + 1..self.len, i);
+        self.len -= 1;
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mailbox, Message, MAILBOX_CAPACITY};
+    use userland::abi::MESSAGE_SIZE;
+
+    fn message(sender: usize, byte: u8) -> Message {
+        Message { sender, data: [byte; MESSAGE_SIZE] }
+    }
+
+    #[test]
+    fn test_mailbox_full() {
+        let mut mailbox = Mailbox::new();
+        for i in 0..MAILBOX_CAPACITY {
+            assert!(mailbox.push(message(1, i as u8)));
+        }
+        assert!(mailbox.is_full());
+        assert!(!mailbox.push(message(1, 0xFF)));
+        assert_eq!(0, mailbox.take(None).unwrap().data[0]);
+        assert!(mailbox.push(message(1, 0xFF)));
+    }
+
+    #[test]
+    fn test_mailbox_take_from_sender() {
+        let mut mailbox = Mailbox::new();
+        mailbox.push(message(1, 10));
+        mailbox.push(message(2, 20));
+        mailbox.push(message(1, 11));
+        mailbox.push(message(2, 21));
+
+        assert_eq!(20, mailbox.take(Some(2)).unwrap().data[0]);
+        assert!(mailbox.take(Some(3)).is_none());
+        // The others keep their order
+        assert_eq!(10, mailbox.take(None).unwrap().data[0]);
+        assert_eq!(11, mailbox.take(None).unwrap().data[0]);
+        assert_eq!(21, mailbox.take(Some(2)).unwrap().data[0]);
+        assert!(mailbox.take(None).is_none());
+    }
+}
@@ -1,24 +1,30 @@
-#![no_main]
-#![no_std]
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(not(test), no_std)]
 #![feature(naked_functions)]
 #![feature(alloc_error_handler)]
 #![feature(const_mut_refs)]
+// The unit tests run on the host, where nothing starts the kernel
+#![cfg_attr(test, allow(dead_code, unused_imports))]
 
 use core::arch::asm;
 use core::ptr;
 use core::panic::PanicInfo;
-use core::mem::MaybeUninit;
 use cortex_m_semihosting::hprintln;
 
 mod systick;
+mod console;
+mod fpu;
+mod mpu;
+mod fault;
 mod process;
-use process::{AlignedStack, Process};
+use process::{AlignedStack, Process, RestartPolicy};
 
 mod linked_list;
 use linked_list::ListItem;
 
 mod scheduler;
 use scheduler::Scheduler;
+mod wait_queue;
 
 mod led;
 use led::LED;
@@ -26,31 +32,38 @@ use led::LED;
 mod vcell;
 
 mod port;
+mod gpio;
 use port::{Port, PortA, PortC};
 
 mod button;
+mod button_service;
 use button::{Button1, Button2, Button3};
 
 mod mutex;
+mod sync;
+mod mailbox;
+mod grant;
 mod allocator;
-mod syscall;
+mod uaccess;
+mod timer;
 
 extern crate alloc;
 use alloc::{alloc::Layout, string::String, format};
-use syscall::{syscall_get_button, syscall_yield, syscall_set_led};
+use userland::abi::ButtonEventKind;
 
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 static GLOBAL_ALLOCATOR: mutex::Mutex<allocator::SimpleAllocator> = mutex::Mutex::new(allocator::SimpleAllocator::new());
 
+#[cfg(not(test))]
 #[alloc_error_handler]
 fn alloc_error_handler(_layout: Layout) -> ! {
     panic!();
 }
 
-const CFSR_ADDR: usize = 0xE000_ED28;
-const SHCSR_ADDR: usize = 0xE000_ED24;
-const HFSR_ADDR: usize = 0xE000_ED2C;
+const HEAP_SIZE: usize = 64 * 1024;
 
+
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern "C" fn Reset() -> ! {
     extern "C" {
@@ -69,8 +82,12 @@ pub unsafe extern "C" fn Reset() -> ! {
 
     hprintln!("Hello World").unwrap();
 
+    fpu::init();
+    fault::init();
+    mpu::init();
+
     let heap_start_addr = &_heap_start as *const u8 as usize;
-    GLOBAL_ALLOCATOR.lock().add_new_node(heap_start_addr, 1024);
+    GLOBAL_ALLOCATOR.lock().add_new_node(heap_start_addr, HEAP_SIZE);
 
     let str: String = format!("heap start is 0x{:x}", heap_start_addr);
     hprintln!("{}", str).unwrap();
@@ -79,41 +96,52 @@ pub unsafe extern "C" fn Reset() -> ! {
     systick::init();
 
     #[link_section = ".app_stack"]
-    static mut APP_STACK: AlignedStack = AlignedStack(MaybeUninit::uninit());
+    static mut APP_STACK: AlignedStack = AlignedStack::new();
     #[link_section = ".app_stack"]
-    static mut APP_STACK2: AlignedStack = AlignedStack(MaybeUninit::uninit());
+    static mut APP_STACK2: AlignedStack<2048> = AlignedStack::new();
     #[link_section = ".app_stack"]
-    static mut APP_STACK3: AlignedStack = AlignedStack(MaybeUninit::uninit());
+    static mut APP_STACK3: AlignedStack = AlignedStack::new();
 
-    let process = Process::new(&mut APP_STACK, app_main);
+    let process = Process::new(&mut APP_STACK, app1_start, 1);
     let mut item = ListItem::new(process);
-    let process2 = Process::new(&mut APP_STACK2, app_main2);
+    let process2 = Process::new(&mut APP_STACK2, app2_start, 1);
     let mut item2 = ListItem::new(process2);
-    let process3 = Process::new(&mut APP_STACK3, app_main3);
+    let mut process3 = Process::new(&mut APP_STACK3, app3_start, 1);
+    process3.set_restart_policy(RestartPolicy::UpTo { max: 2, backoff_ms: 1000 });
     let mut item3 = ListItem::new(process3);
     let mut scheduler = Scheduler::new();
 
     scheduler.push(&mut item);
     scheduler.push(&mut item2);
     scheduler.push(&mut item3);
+    scheduler.set_time_slice(10);
 
     scheduler.exec();
 }
 
+#[cfg(not(test))]
 #[link_section = ".vector_table.reset_vector"]
 #[no_mangle]
 pub static RESET_VECTOR: unsafe extern "C" fn() -> ! = Reset;
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(_panic: &PanicInfo<'_>) -> ! {
     loop {}
 }
 
+#[cfg(not(test))]
+extern "C" {
+    // The context switch lives in asm.s
+    fn PendSV();
+}
+
 pub union Vector {
     reserved: u32,
     handler: unsafe extern "C" fn(),
 }
 
+#[cfg(not(test))]
 #[link_section = ".vector_table.exceptions"]
 #[no_mangle]
 pub static EXCEPTIONS: [Vector; 14] = [
@@ -145,87 +173,84 @@ pub extern "C" fn NMI() {
     hprintln!("NMI").unwrap();
     loop {}
 }
-#[no_mangle]
-pub unsafe extern "C" fn HardFault() {
-    hprintln!("HardFault").unwrap();
-    hprintln!("CFSR:{:X}", ptr::read_volatile(CFSR_ADDR as *mut u32)).unwrap();
-    hprintln!("SHCSR:{:X}", ptr::read_volatile(SHCSR_ADDR as *mut u32)).unwrap();
-    hprintln!("HFSR:{:X}", ptr::read_volatile(HFSR_ADDR as *mut u32)).unwrap();
-    loop {}
-}
-#[no_mangle]
-pub extern "C" fn MemManage() {
-    hprintln!("MemManage").unwrap();
-    loop {}
-}
-#[no_mangle]
-pub extern "C" fn BusFault() {
-    hprintln!("BusFault").unwrap();
-    loop {}
-}
-#[no_mangle]
-pub extern "C" fn UsageFault() {
-    hprintln!("UsageFault").unwrap();
-    loop {}
+// The fault handlers pass their EXC_RETURN and the stack holding the
+// exception frame to fault.rs, and return through it
+macro_rules! fault_trampolines {
+    ($($name:ident => $handler:path),*) => {
+        $(
+            #[cfg(not(test))]
+            #[no_mangle]
+            #[naked]
+            pub unsafe extern "C" fn $name() {
+                asm!(
+                    "mov r0, lr",
+                    "tst lr, #4",
+                    "ite eq",
+                    "mrseq r1, msp",
+                    "mrsne r1, psp",
+                    "b {handler}",
+                    handler = sym $handler,
+                    options(noreturn),
+                );
+            }
+        )*
+    };
 }
 
-#[no_mangle]
-#[naked]
-pub unsafe extern "C" fn SVCall() {
-    asm!(
-        "cmp lr, #0xfffffff9",
-        "bne 1f",
-
-        "mov r0, #1",
-        "msr CONTROL, r0",
-        "isb",
-        "movw lr, #0xfffd",
-        "movt lr, #0xffff",
-        "bx lr",
-
-        "1:",
-        "mov r0, #0",
-        "msr CONTROL, r0",
-        "isb",
-        "movw lr, #0xfff9",
-        "movt lr, #0xffff",
-        "bx lr",
-        options(noreturn),
-    );
-}
+fault_trampolines!(
+    HardFault => fault::hard_fault,
+    MemManage => fault::mem_manage,
+    BusFault => fault::bus_fault,
+    UsageFault => fault::usage_fault
+);
 
 #[no_mangle]
-pub extern "C" fn PendSV() {
-    hprintln!("PendSV").unwrap();
-    loop {}
+pub extern "C" fn SVCall() {
+    scheduler::request_switch(scheduler::SWITCH_SYSCALL);
 }
 
 #[no_mangle]
 pub extern "C" fn SysTick() {
-    hprintln!("Sysick").unwrap();
+    let now = systick::tick();
+    button_service::tick();
+    scheduler::tick(now);
 }
 
-extern "C" fn app_main() -> ! {
+fn app_main(_arg: usize) -> u32 {
     loop {
-        hprintln!("App1").unwrap();
-        while !syscall_get_button() {}
-        syscall_yield();
+        match userland::wait_button_event(0b110) {
+            Ok(event) => userland::println!("App1 {:?}", event),
+            Err(error) => userland::println!("App1 {:?}", error),
+        }
     }
 }
+userland::entry!(app1_start, app_main);
 
-extern "C" fn app_main2() -> ! {
+fn app_main2(_arg: usize) -> u32 {
+    match userland::spawn(worker_start, 42, 2, 1024).and_then(userland::join) {
+        Ok(code) => userland::println!("Worker exited with {}", code),
+        Err(error) => userland::println!("Worker failed: {:?}", error),
+    }
     loop {
-        hprintln!("App2").unwrap();
-        syscall_set_led(true);
-        while !syscall_get_button() {}
-        syscall_yield();
+        userland::println!("App2");
+        userland::set_led(true);
+        while userland::wait_button_event(0b001).map_or(false, |e| e.kind != ButtonEventKind::Press) {}
     }
 }
+userland::entry!(app2_start, app_main2);
 
-extern "C" fn app_main3() -> ! {
-    loop {
-        hprintln!("App3").unwrap();
-        syscall_set_led(false);
-        syscall_yield();
+fn app_main3(_arg: usize) -> u32 {
+    for _ in 0..10 {
+        userland::println!("App3 {}ms", userland::get_time());
+        userland::set_led(false);
+        userland::sleep(500);
     }
+    0
+}
+userland::entry!(app3_start, app_main3);
+
+fn worker_main(arg: usize) -> u32 {
+    userland::println!("Worker {} uses {} bytes of stack", arg, userland::stack_usage());
+    arg as u32
 }
+userland::entry!(worker_start, worker_main);
@@ -0,0 +1,174 @@
+use cortex_m_semihosting::hprintln;
+use core::arch::asm;
+use core::ptr::write_volatile;
+
+const MPU_CTRL_ADDR: usize = 0xE000_ED94;
+const MPU_RBAR_ADDR: usize = 0xE000_ED9C;
+const MPU_RASR_ADDR: usize = 0xE000_EDA0;
+
+const MPU_CTRL_ENABLE: u32 = 1 << 0;
+// The kernel keeps the default memory map
+const MPU_CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+const RBAR_VALID: u32 = 1 << 4;
+const RASR_ENABLE: u32 = 1 << 0;
+const RASR_XN: u32 = 1 << 28;
+const RASR_AP_PRIV_RW_UNPRIV_RO: u32 = 0b010 << 24;
+const RASR_AP_FULL_ACCESS: u32 = 0b011 << 24;
+const RASR_AP_READ_ONLY: u32 = 0b110 << 24;
+// TEX=0b000, S=1, C=1, B=1: shareable write-back SRAM
+const RASR_SRAM: u32 = 0b000111 << 16;
+// TEX=0b000, C=1, B=0: write-through flash
+const RASR_FLASH: u32 = 0b000010 << 16;
+
+const FLASH_START: usize = 0x0000_0000;
+const FLASH_SIZE: usize = 512 * 1024;
+
+const FLASH_REGION: u32 = 0;
+const FIRST_PROCESS_REGION: u32 = 1;
+const NUM_REGIONS: u32 = 8;
+/// Regions reloaded on every switch: the process's stack plus its grants.
+pub const NUM_PROCESS_REGIONS: usize = (NUM_REGIONS - FIRST_PROCESS_REGION) as usize;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// A memory range in the form the MPU registers take it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Region {
+    start: usize,
+    size: usize,
+    access: Access,
+}
+
+impl Region {
+    /// The MPU can only protect a power-of-two size of 32 bytes or more,
+    /// aligned to that size. Returns None for any other range.
+    pub fn new(start: usize, size: usize, access: Access) -> Option<Self> {
+        if size < 32 || !size.is_power_of_two() || start % size != 0 {
+            return None;
+        }
+        Some(Region { start, size, access })
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn access(&self) -> Access {
+        self.access
+    }
+
+    pub fn contains(&self, start: usize, len: usize) -> bool {
+        start >= self.start && start.checked_add(len).map_or(false, |end| end <= self.start + self.size)
+    }
+
+    fn rasr(&self) -> u32 {
+        let ap = match self.access {
+            Access::ReadOnly => RASR_AP_PRIV_RW_UNPRIV_RO,
+            Access::ReadWrite => RASR_AP_FULL_ACCESS,
+        };
+        RASR_XN | ap | RASR_SRAM | size_field(self.size) | RASR_ENABLE
+    }
+}
+
+/// Whether the range lies in the flash, which every process may read.
+pub fn is_flash(start: usize, len: usize) -> bool {
+    let flash = FLASH_START..=FLASH_START + FLASH_SIZE;
+    flash.contains(&start) && start.checked_add(len).map_or(false, |end| flash.contains(&end))
+}
+
+/// Whether `regions` plus the flash let an unprivileged access of `len` bytes at `start`.
+pub fn allows(regions: &[Option<Region>], start: usize, len: usize, access: Access) -> bool {
+    if access == Access::ReadOnly && is_flash(start, len) {
+        return true;
+    }
+    regions.iter().flatten()
+        .filter(|r| access == Access::ReadOnly || r.access() == Access::ReadWrite)
+        .any(|r| r.contains(start, len))
+}
+
+// SIZE is log2(size) - 1
+fn size_field(size: usize) -> u32 {
+    (size.trailing_zeros() - 1) << 1
+}
+
+fn write_region(number: u32, rbar: u32, rasr: u32) {
+    unsafe {
+        write_volatile(MPU_RBAR_ADDR as *mut u32, rbar | RBAR_VALID | number);
+        write_volatile(MPU_RASR_ADDR as *mut u32, rasr);
+    }
+}
+
+/// Let processes execute and read the flash.
+/// Everything else is only reachable through the regions of `configure`.
+pub fn init() {
+    hprintln!("MPU init").unwrap();
+    write_region(FLASH_REGION, FLASH_START as u32, RASR_AP_READ_ONLY | RASR_FLASH | size_field(FLASH_SIZE) | RASR_ENABLE);
+    configure(&[]);
+    unsafe {
+        write_volatile(MPU_CTRL_ADDR as *mut u32, MPU_CTRL_ENABLE | MPU_CTRL_PRIVDEFENA);
+        asm!("dsb", "isb");
+    }
+}
+
+/// Load the regions of the process that is about to run.
+pub fn configure(regions: &[Option<Region>]) {
+    for i in 0..NUM_PROCESS_REGIONS {
+        let number = FIRST_PROCESS_REGION + i as u32;
+        match regions.get(i).copied().flatten() {
+            Some(region) => write_region(number, region.start() as u32, region.rasr()),
+            None => write_region(number, 0, 0),
+        }
+    }
+    unsafe {
+        asm!("dsb", "isb");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allows, Access, Region, FLASH_SIZE};
+
+    #[test]
+    fn test_region_new() {
+        assert!(Region::new(0x2000_0000, 1024, Access::ReadWrite).is_some());
+        assert!(Region::new(0x2000_0000, 16, Access::ReadWrite).is_none());
+        assert!(Region::new(0x2000_0000, 1000, Access::ReadWrite).is_none());
+        assert!(Region::new(0x2000_0200, 1024, Access::ReadWrite).is_none());
+    }
+
+    #[test]
+    fn test_allows_bounds() {
+        let stack = Region::new(0x2000_0000, 1024, Access::ReadWrite);
+        let regions = [stack, None];
+
+        assert!(allows(&regions, 0x2000_0000, 1024, Access::ReadWrite));
+        assert!(allows(&regions, 0x2000_03F0, 16, Access::ReadWrite));
+        assert!(!allows(&regions, 0x2000_03F0, 17, Access::ReadWrite));
+        assert!(!allows(&regions, 0x1FFF_FFFF, 2, Access::ReadOnly));
+        assert!(!allows(&regions, 0x2000_0000, usize::MAX, Access::ReadOnly));
+    }
+
+    #[test]
+    fn test_allows_flash() {
+        // The flash is readable but never writable
+        assert!(allows(&[], 0x100, 4, Access::ReadOnly));
+        assert!(!allows(&[], 0x100, 4, Access::ReadWrite));
+        assert!(!allows(&[], FLASH_SIZE - 2, 4, Access::ReadOnly));
+    }
+
+    #[test]
+    fn test_allows_grant() {
+        let stack = Region::new(0x2000_0000, 1024, Access::ReadWrite);
+        let grant = Region::new(0x2000_1000, 32, Access::ReadOnly);
+        let regions = [stack, grant];
+
+        assert!(allows(&regions, 0x2000_1000, 32, Access::ReadOnly));
+        assert!(!allows(&regions, 0x2000_1000, 32, Access::ReadWrite));
+        assert!(!allows(&regions, 0x2000_1000, 33, Access::ReadOnly));
+    }
+}
@@ -3,8 +3,13 @@ use crate::vcell::VolatileCell;
 
 pub trait PortId {
     const ADDR: usize;
+    // Position among the ports, as the GPIO syscalls number them
+    const INDEX: usize;
 }
 
+pub const NUM_PORTS: usize = 4;
+pub const PINS_PER_PORT: usize = 32;
+
 pub struct Pin<P: PortId, const N: usize> {
     _port_id: PhantomData<P>,
 }
@@ -105,48 +110,139 @@ pub struct PortRegisters {
     pub pincfg: [VolatileCell<u8>; 32],
 }
 pub struct PortA {}
+pub struct PortB {}
 pub struct PortC {}
+pub struct PortD {}
 
 impl PortId for PortA {
     const ADDR: usize = 0x4100_8000;
+    const INDEX: usize = 0;
+}
+
+impl PortId for PortB {
+    const ADDR: usize = 0x4100_8080;
+    const INDEX: usize = 1;
 }
 
 impl PortId for PortC {
     const ADDR: usize = 0x4100_8100;
+    const INDEX: usize = 2;
 }
 
+impl PortId for PortD {
+    const ADDR: usize = 0x4100_8180;
+    const INDEX: usize = 3;
+}
+
+const PORT_ADDRS: [usize; NUM_PORTS] = [PortA::ADDR, PortB::ADDR, PortC::ADDR, PortD::ADDR];
+
+// PINCFG bits
+const PINCFG_INEN: u8 = 1 << 1;
+const PINCFG_PULLEN: u8 = 1 << 2;
+
 impl<P: PortId, const N: usize> Pin<P, N> {
     pub fn new() -> Self {
         Self { _port_id: PhantomData }
     }
 
-    pub fn registers<'a>(&'a self) -> &'a PortRegisters {
-        let registers = P::ADDR as *const PortRegisters;
-        unsafe { &*registers }
+    /// The same pin as a `DynPin`, which does the register access.
+    pub fn dyn_pin(&self) -> DynPin {
+        DynPin { port: P::INDEX, n: N }
+    }
+
+    pub fn registers(&self) -> &'static PortRegisters {
+        self.dyn_pin().registers()
     }
 
     pub fn clear_dir(&self) {
-        self.registers().dirclr.write(1 << N);
+        self.dyn_pin().clear_dir();
     }
 
     pub fn set_dir(&self) {
-        self.registers().dirset.write(1 << N);
+        self.dyn_pin().set_dir();
     }
 
     pub fn set_out(&self) {
-        self.registers().outset.write(1 << N);
+        self.dyn_pin().set_out();
     }
 
     pub fn clear_out(&self) {
-        self.registers().outclr.write(1 << N);
+        self.dyn_pin().clear_out();
     }
 
     pub fn enable_floating_input(&self) {
-        // Set INEN as 0b1
-        self.registers().pincfg[N].write(1 << 1);
+        self.dyn_pin().enable_input(Pull::None);
+    }
+
+    pub fn get_in(&self) -> bool {
+        self.dyn_pin().get_in()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// A pin chosen at runtime, for the GPIO syscalls.
+pub struct DynPin {
+    port: usize,
+    n: usize,
+}
+
+impl DynPin {
+    /// Returns None when the port or the pin does not exist.
+    pub fn new(port: usize, n: usize) -> Option<Self> {
+        if port >= NUM_PORTS || n >= PINS_PER_PORT {
+            return None;
+        }
+        Some(Self { port, n })
+    }
+
+    pub fn registers(&self) -> &'static PortRegisters {
+        let registers = PORT_ADDRS[self.port] as *const PortRegisters;
+        unsafe { &*registers }
+    }
+
+    pub fn clear_dir(&self) {
+        self.registers().dirclr.write(1 << self.n);
+    }
+
+    pub fn set_dir(&self) {
+        self.registers().dirset.write(1 << self.n);
+    }
+
+    pub fn set_out(&self) {
+        self.registers().outset.write(1 << self.n);
+    }
+
+    pub fn clear_out(&self) {
+        self.registers().outclr.write(1 << self.n);
+    }
+
+    pub fn toggle_out(&self) {
+        self.registers().outtgl.write(1 << self.n);
+    }
+
+    /// Enable the input buffer with a pull resistor.
+    /// With the pin as input, OUT selects between pull-up and pull-down.
+    pub fn enable_input(&self, pull: Pull) {
+        match pull {
+            Pull::None => self.registers().pincfg[self.n].write(PINCFG_INEN),
+            Pull::Up => {
+                self.set_out();
+                self.registers().pincfg[self.n].write(PINCFG_INEN | PINCFG_PULLEN);
+            },
+            Pull::Down => {
+                self.clear_out();
+                self.registers().pincfg[self.n].write(PINCFG_INEN | PINCFG_PULLEN);
+            },
+        }
     }
 
     pub fn get_in(&self) -> bool {
-        self.registers().r#in.read() & (1 << N) != 0
+        self.registers().r#in.read() & (1 << self.n) != 0
     }
 }
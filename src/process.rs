@@ -1,7 +1,14 @@
 use core::marker::PhantomData;
 use core::mem::MaybeUninit;
+use crate::linked_list::ListItem;
+use crate::mailbox::Mailbox;
+use crate::mpu::{self, Access, Region, NUM_PROCESS_REGIONS};
+use crate::scheduler::NUM_PRIORITIES;
+use crate::timer::Timer;
+use userland::abi::{self, SyscallResult};
 
 #[repr(C)]
+#[derive(Clone, Copy, Debug)]
 pub struct ContextFrame {
     pub r0: u32,
     pub r1: u32,
@@ -13,43 +20,374 @@ pub struct ContextFrame {
     pub xpsr: u32,
 }
 
+/// Entry point of a process. It receives its initial argument in r0, and
+/// returning from it exits the process with the returned code.
+pub type ProcessEntry = userland::abi::Entry;
+
+const EXC_RETURN_THREAD_PSP: u32 = 0xFFFF_FFFD;
+
+/// Registers that are not part of the exception frame.
+/// The layout is shared with `asm_execute_process`.
+#[repr(C)]
+pub struct SavedRegisters {
+    // r4-r11
+    regs: [u32; 8],
+    // EXC_RETURN used to resume the process. Bit 4 is clear while it has an FP context.
+    exc_return: u32,
+    // s16-s31, only saved and restored while the FP context is active
+    fp_regs: [u32; 16],
+}
+
+// Processes return here from their entry point, with the exit code in r0
+extern "C" fn return_trampoline(code: u32) -> ! {
+    userland::exit(code)
+}
+
+/// What the kernel does when a process exits or is killed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RestartPolicy {
+    Never,
+    Always,
+    /// Restart at most `max` times, waiting `backoff_ms` before the first
+    /// restart and twice as long before each further one.
+    UpTo { max: u32, backoff_ms: u32 },
+}
+
+impl RestartPolicy {
+    /// Milliseconds to wait before the next restart, or None to stay terminated.
+    pub fn restart_delay(&self, restarts: u32) -> Option<u32> {
+        match *self {
+            RestartPolicy::Never => None,
+            RestartPolicy::Always => Some(0),
+            RestartPolicy::UpTo { max, .. } if restarts >= max => None,
+            RestartPolicy::UpTo { backoff_ms, .. } => Some(backoff_ms.saturating_mul(1 << restarts.min(31))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProcessState {
+    Ready,
+    Running,
+    Blocked,
+    Exited,
+    Faulted,
+}
+
 pub struct Process<'a> {
+    id: usize,
+    state: ProcessState,
+    // Priority it runs at. Higher than `base_priority` while it holds a mutex that a higher priority waits for.
+    priority: usize,
+    base_priority: usize,
+    // Kept to start the process over when it is restarted
+    entry: ProcessEntry,
+    arg: usize,
+    restart_policy: RestartPolicy,
+    restarts: u32,
+    mailbox: Mailbox,
+    // Links the process into the timer list while it waits with a deadline
+    timer: ListItem<'a, Timer>,
+    stack_bottom: usize,
+    stack_size: usize,
+    // Spawned at runtime: the kernel frees the stack and the process when it exits
+    heap_stack: bool,
+    // MPU regions the process may access. The first one is its stack.
+    regions: [Option<Region>; NUM_PROCESS_REGIONS],
     sp: usize,
-    regs: [u32; 8],
+    saved: SavedRegisters,
     marker: PhantomData<&'a u8>,
 }
 
-#[repr(align(8))]
-pub struct AlignedStack(pub MaybeUninit<[u8; 1024]>);
+/// Maps a stack size to a type with the same alignment.
+/// The MPU can only protect a stack that is aligned to its size.
+pub trait StackAlignment {
+    type Align: Copy;
+}
+
+pub struct StackSize<const N: usize>;
+
+macro_rules! stack_alignments {
+    ($($name:ident = $size:literal),*) => {
+        $(
+            #[repr(align($size))]
+            #[derive(Clone, Copy)]
+            pub struct $name;
+
+            impl StackAlignment for StackSize<$size> {
+                type Align = $name;
+            }
+        )*
+    };
+}
+
+stack_alignments!(Align256 = 256, Align512 = 512, Align1K = 1024, Align2K = 2048, Align4K = 4096, Align8K = 8192, Align16K = 16384);
+
+#[repr(C)]
+pub struct AlignedStack<const N: usize = 1024> where StackSize<N>: StackAlignment {
+    _align: [<StackSize<N> as StackAlignment>::Align; 0],
+    data: MaybeUninit<[u8; N]>,
+}
+
+impl<const N: usize> AlignedStack<N> where StackSize<N>: StackAlignment {
+    pub const fn new() -> Self {
+        AlignedStack {
+            _align: [],
+            data: MaybeUninit::uninit(),
+        }
+    }
+}
+
+/// Alignment the hardware requires for the stack pointer on exception entry.
+pub const STACK_ALIGN: usize = 8;
+/// Smallest stack that can hold the initial frame plus an FP exception frame.
+pub const MIN_STACK_SIZE: usize = 256;
+
+// Guard word at the lowest address of every stack
+const STACK_CANARY: u32 = 0x5AFE_57AC;
+// Fills the unused part of a stack, so the deepest write can be found later
+const STACK_PAINT: u32 = 0xCCCC_CCCC;
 
 extern "C" {
-    fn asm_execute_process(sp: usize, regs: &mut [u32; 8]) -> usize;
+    fn asm_execute_process(sp: usize, saved: &mut SavedRegisters) -> usize;
 }
 
 impl<'a> Process<'a> {
-    pub fn new(stack: &'a mut AlignedStack, app_main: extern "C" fn() -> !) -> Self {
-        let sp = (stack.0.as_ptr() as usize) + unsafe { stack.0.assume_init_ref().len() } - 0x20;
-        let context_frame: &mut ContextFrame = unsafe {
-            &mut *(sp as *mut ContextFrame)
+    pub fn new<const N: usize>(stack: &'a mut AlignedStack<N>, app_main: ProcessEntry, priority: usize) -> Self
+    where
+        StackSize<N>: StackAlignment,
+    {
+        let stack = unsafe { &mut *(stack.data.as_mut_ptr() as *mut [MaybeUninit<u8>; N]) };
+        Self::with_stack(stack, app_main, priority)
+    }
+
+    /// Create a process on a stack whose size is only known at runtime.
+    /// The size must be a power of two and the stack aligned to it.
+    /// A priority past the lowest one is lowered to it.
+    pub fn with_stack(stack: &'a mut [MaybeUninit<u8>], app_main: ProcessEntry, priority: usize) -> Self {
+        assert!(stack.len() >= MIN_STACK_SIZE);
+        let priority = priority.min(NUM_PRIORITIES - 1);
+        let stack_bottom = stack.as_ptr() as usize;
+        let stack_size = stack.len();
+        let stack_region = Region::new(stack_bottom, stack_size, Access::ReadWrite);
+        assert!(stack_region.is_some());
+        let mut regions = [None; NUM_PROCESS_REGIONS];
+        regions[0] = stack_region;
+
+        let mut process = Process {
+            id: 0,
+            state: ProcessState::Ready,
+            priority,
+            base_priority: priority,
+            entry: app_main,
+            arg: 0,
+            restart_policy: RestartPolicy::Never,
+            restarts: 0,
+            mailbox: Mailbox::new(),
+            timer: ListItem::new(Timer { deadline: 0, process: 0 }),
+            stack_bottom,
+            stack_size,
+            heap_stack: false,
+            regions,
+            sp: 0,
+            saved: SavedRegisters {
+                regs: [0; 8],
+                exc_return: EXC_RETURN_THREAD_PSP,
+                fp_regs: [0; 16],
+            },
+            marker: PhantomData,
         };
-        context_frame.r0 = 0;
+        process.init_stack();
+        process
+    }
+
+    // Paint the stack and push the frame that starts the entry point
+    fn init_stack(&mut self) {
+        let stack_top = (self.stack_bottom + self.stack_size) & !(STACK_ALIGN - 1);
+        self.sp = stack_top - 0x20;
+        unsafe {
+            let words = self.stack_bottom as *mut u32;
+            words.write(STACK_CANARY);
+            for i in 1..(self.sp - self.stack_bottom) / 4 {
+                words.add(i).write(STACK_PAINT);
+            }
+        }
+        let context_frame = self.get_context_frame();
+        context_frame.r0 = self.arg as u32;
         context_frame.r1 = 0;
         context_frame.r2 = 0;
         context_frame.r3 = 0;
         context_frame.r12 = 0;
-        context_frame.lr = 0;
-        context_frame.return_addr = app_main as u32;
+        context_frame.lr = return_trampoline as u32;
+        context_frame.return_addr = self.entry as u32;
         context_frame.xpsr = 0x0100_0000;
+        self.saved.regs = [0; 8];
+        self.saved.exc_return = EXC_RETURN_THREAD_PSP;
+    }
 
-        Process {
-            sp,
-            regs: [0; 8],
-            marker: PhantomData,
+    /// Start the process over from its entry point with the same argument.
+    pub fn restart(&mut self) {
+        self.init_stack();
+        self.mailbox = Mailbox::new();
+        self.priority = self.base_priority;
+        self.restarts += 1;
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn set_id(&mut self, id: usize) {
+        self.id = id;
+        self.timer.process = id;
+    }
+
+    /// The timer of the process. It waits for at most one deadline at a time.
+    pub fn timer(&mut self) -> &'a mut ListItem<'a, Timer> {
+        // The scheduler cancels the timer before it restarts or frees the process
+        unsafe { &mut *(&mut self.timer as *mut ListItem<'a, Timer>) }
+    }
+
+    pub fn set_state(&mut self, state: ProcessState) {
+        self.state = state;
+    }
+
+    pub fn priority(&self) -> usize {
+        self.priority
+    }
+
+    pub fn set_priority(&mut self, priority: usize) {
+        self.priority = priority;
+    }
+
+    /// Priority the process asked for, before inheritance.
+    pub fn base_priority(&self) -> usize {
+        self.base_priority
+    }
+
+    pub fn set_base_priority(&mut self, priority: usize) {
+        self.base_priority = priority;
+    }
+
+    pub fn restart_policy(&self) -> RestartPolicy {
+        self.restart_policy
+    }
+
+    pub fn set_restart_policy(&mut self, restart_policy: RestartPolicy) {
+        self.restart_policy = restart_policy;
+    }
+
+    /// Number of times the process has been restarted.
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    pub fn mailbox_mut(&mut self) -> &mut Mailbox {
+        &mut self.mailbox
+    }
+
+    pub fn stack_bottom(&self) -> usize {
+        self.stack_bottom
+    }
+
+    pub fn stack_size(&self) -> usize {
+        self.stack_size
+    }
+
+    /// False once the process has written over the guard word or moved
+    /// its stack pointer below it.
+    pub fn is_stack_intact(&self) -> bool {
+        let canary = unsafe { (self.stack_bottom as *const u32).read_volatile() };
+        canary == STACK_CANARY && self.sp >= self.stack_bottom + 4
+    }
+
+    /// Whether the stack pointer leaves room for an exception frame inside the stack.
+    /// It does not after a fault while stacking.
+    pub fn has_valid_frame(&self) -> bool {
+        let stack_top = (self.stack_bottom + self.stack_size) & !(STACK_ALIGN - 1);
+        self.sp >= self.stack_bottom && self.sp + 0x20 <= stack_top
+    }
+
+    /// Deepest stack usage so far, in bytes.
+    pub fn peak_stack_usage(&self) -> usize {
+        let stack_top = (self.stack_bottom + self.stack_size) & !(STACK_ALIGN - 1);
+        let mut addr = self.stack_bottom + 4;
+        while addr < stack_top && unsafe { (addr as *const u32).read_volatile() } == STACK_PAINT {
+            addr += 4;
         }
+        stack_top - addr
+    }
+
+    pub fn regions(&self) -> &[Option<Region>] {
+        &self.regions
+    }
+
+    /// Whether the process may access `len` bytes at `start` for `access`.
+    /// It may read its stack, its grants and the flash, and write its
+    /// stack and the grants it may write.
+    pub fn can_access(&self, start: usize, len: usize, access: Access) -> bool {
+        mpu::allows(&self.regions, start, len, access)
+    }
+
+    /// Let the process access memory outside of its stack.
+    /// Returns false when all regions are in use.
+    pub fn grant(&mut self, region: Region) -> bool {
+        match self.regions.iter_mut().skip(1).find(|r| r.is_none()) {
+            Some(slot) => {
+                *slot = Some(region);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Take back a region given with `grant`.
+    pub fn revoke(&mut self, region: Region) {
+        if let Some(slot) = self.regions.iter_mut().skip(1).find(|r| **r == Some(region)) {
+            *slot = None;
+        }
+    }
+
+    /// Whether the range lies in the stack of the process, the only memory it owns.
+    pub fn owns(&self, start: usize, len: usize) -> bool {
+        self.regions[0].map_or(false, |stack| stack.contains(start, len))
+    }
+
+    pub fn has_heap_stack(&self) -> bool {
+        self.heap_stack
+    }
+
+    pub fn set_heap_stack(&mut self, heap_stack: bool) {
+        self.heap_stack = heap_stack;
+    }
+
+    /// Set the value the entry point receives as its argument.
+    pub fn set_argument(&mut self, arg: usize) {
+        self.arg = arg;
+        self.get_context_frame().r0 = arg as u32;
+    }
+
+    /// Number of the last syscall. It stays in the frame until its result is set.
+    pub fn syscall_number(&self) -> u32 {
+        unsafe { (*(self.sp as *const ContextFrame)).r0 }
+    }
+
+    /// Arguments of the last syscall. They stay in the frame until its result is set.
+    pub fn syscall_args(&self) -> [u32; 4] {
+        let frame = unsafe { &*(self.sp as *const ContextFrame) };
+        [frame.r1, frame.r2, frame.r3, frame.r12]
+    }
+
+    /// Return `result` from the last syscall, in r0 and r1.
+    pub fn set_syscall_result(&mut self, result: SyscallResult) {
+        let (status, value) = abi::encode_result(result);
+        let frame = self.get_context_frame();
+        frame.r0 = status;
+        frame.r1 = value;
     }
 
     pub fn exec(&mut self) {
-        self.sp = unsafe { asm_execute_process(self.sp, &mut self.regs) }
+        self.sp = unsafe { asm_execute_process(self.sp, &mut self.saved) }
     }
 
     pub fn get_context_frame(&mut self) -> &'a mut ContextFrame {
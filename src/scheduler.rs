@@ -1,68 +1,1338 @@
-use crate::button::Button1;
+use core::arch::asm;
+use core::mem::{transmute, MaybeUninit};
+use core::slice;
+use core::ptr::write_volatile;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use cortex_m_semihosting::hprintln;
+use alloc::alloc::{alloc, dealloc, Layout};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use alloc::format;
+use crate::button_service;
+use crate::console::{Console, Semihosting};
+use crate::fault;
+use crate::gpio::{self, PinOwner, PinTable};
 use crate::led::LED;
 use crate::linked_list::{LinkedList, ListItem};
-use crate::process::Process;
+use crate::grant::Grant;
+use crate::mailbox::Message;
+use crate::process::{AlignedStack, Process, ProcessEntry, ProcessState, MIN_STACK_SIZE};
 use crate::port::{Port, PortA, PortC};
+use crate::mpu::{self, Access, Region};
+use crate::sync::{self, EventFlags, Mutex, Semaphore, MAX_EVENT_GROUPS};
+use crate::systick;
+use crate::timer::{Timer, TimerList};
+use crate::uaccess::{self, CHUNK_SIZE};
+use userland::abi::{
+    self, ButtonEvent, PinMode, Syscall, SyscallError, SyscallResult, ANY_SENDER, BUTTON_EVENT_FLAGS, EVENT_CLEAR, EVENT_WAIT_ALL,
+    CURRENT_PROCESS, MESSAGE_SIZE, NUM_BUTTONS, WAIT_FOREVER,
+};
+use crate::wait_queue::WaitQueue;
+
+const DEFAULT_TIME_SLICE: u32 = 1;
+
+const ICSR_ADDR: usize = 0xE000_ED04;
+const ICSR_PENDSVSET: u32 = 1 << 28;
+const SHPR3_PENDSV_ADDR: usize = 0xE000_ED22;
+const LOWEST_PRIORITY: u8 = 0xFF;
+
+/// The process issued a syscall.
+pub const SWITCH_SYSCALL: u32 = 1 << 0;
+/// The process used up its time slice.
+pub const SWITCH_PREEMPT: u32 = 1 << 1;
+/// A timer is due: a sleep ends or a wait times out.
+pub const SWITCH_WAKEUP: u32 = 1 << 2;
+/// The process faulted. See `fault::take`.
+pub const SWITCH_FAULT: u32 = 1 << 3;
+/// A button event is waiting for the kernel. See `button_service::pop`.
+pub const SWITCH_BUTTON: u32 = 1 << 4;
+/// An interrupt handler set event flags. See `sync::set_flags_from_isr`.
+pub const SWITCH_EVENT: u32 = 1 << 5;
+
+// Ticks left for the running process. Reloaded by the kernel on every switch.
+static TIME_SLICE_REMAINING: AtomicU32 = AtomicU32::new(DEFAULT_TIME_SLICE);
+// Why the running process was switched out. Cleared by the kernel after every switch.
+static SWITCH_REASON: AtomicU32 = AtomicU32::new(0);
+// First deadline of the timer list, published by the kernel.
+static HAS_TIMERS: AtomicBool = AtomicBool::new(false);
+static NEXT_DEADLINE: AtomicU32 = AtomicU32::new(0);
+
+#[cfg(not(test))]
+fn process_running() -> bool {
+    // CONTROL.nPRIV is only set while a process owns the thread mode
+    let control: u32;
+    unsafe {
+        asm!("mrs {}, CONTROL", out(reg) control);
+    }
+    control & 1 != 0
+}
+
+// The host tests never run a process
+#[cfg(test)]
+fn process_running() -> bool {
+    false
+}
+
+/// Ask PendSV to switch from the running process back to the kernel.
+/// Can be called from any handler, including SVCall and SysTick.
+/// Does nothing while the kernel itself is running.
+pub fn request_switch(reason: u32) {
+    if !process_running() {
+        return;
+    }
+    SWITCH_REASON.fetch_or(reason, Ordering::Relaxed);
+    unsafe {
+        write_volatile(ICSR_ADDR as *mut u32, ICSR_PENDSVSET);
+    }
+}
+
+/// Called from the SysTick handler with the updated tick count.
+pub fn tick(now: u32) {
+    if !process_running() {
+        return;
+    }
+    if HAS_TIMERS.load(Ordering::Relaxed) && systick::is_reached(NEXT_DEADLINE.load(Ordering::Relaxed), now) {
+        request_switch(SWITCH_WAKEUP);
+    }
+    let remaining = TIME_SLICE_REMAINING.load(Ordering::Relaxed).saturating_sub(1);
+    TIME_SLICE_REMAINING.store(remaining, Ordering::Relaxed);
+    if remaining == 0 {
+        request_switch(SWITCH_PREEMPT);
+    }
+}
+
+pub const NUM_PRIORITIES: usize = 4;
+
+const IDLE_ID: usize = usize::MAX;
+
+/// Exit code recorded for a process the kernel terminated.
+pub const EXIT_KILLED: u32 = u32::MAX;
+
+#[link_section = ".app_stack"]
+static mut IDLE_STACK: AlignedStack<256> = AlignedStack::new();
+
+extern "C" fn idle_main(_arg: usize) -> u32 {
+    loop {
+        unsafe {
+            asm!("wfi");
+        }
+    }
+}
+
+// The MPU needs a power-of-two size and the stack aligned to it
+fn stack_layout(stack_size: usize) -> Option<Layout> {
+    let size = stack_size.max(MIN_STACK_SIZE).checked_next_power_of_two()?;
+    Layout::from_size_align(size, size).ok()
+}
+
+// Peripherals that syscalls operate on, owned by the kernel loop
+struct Devices<'p> {
+    led: LED<'p>,
+}
+
+// Button events kept until a process waits for them
+const MAX_PENDING_BUTTON_EVENTS: usize = 8;
+
+const MAX_SEMAPHORES: usize = 16;
+const MAX_MUTEXES: usize = 16;
+const MAX_GRANTS: usize = 16;
+const MAX_EXIT_CODES: usize = 16;
+
+type SyscallHandler<'a> = fn(&mut Scheduler<'a>, &Devices, [u32; 4]) -> SyscallResult;
+
+// Buffer and sender that a process blocked in receive or call waits for
+fn receive_args(process: &Process) -> (usize, u32) {
+    let args = process.syscall_args();
+    if process.syscall_number() == Syscall::Call as u32 {
+        (args[2] as usize, args[0])
+    } else {
+        (args[0] as usize, args[1])
+    }
+}
+
+fn accepts(process: &Process, sender: usize) -> bool {
+    let (_, from) = receive_args(process);
+    from == ANY_SENDER || from as usize == sender
+}
+
+// Timeouts further ahead would wrap past the tick counter
+fn check_timeout(timeout: u32) -> Result<(), SyscallError> {
+    if timeout > systick::MAX_TICKS_AHEAD && timeout != WAIT_FOREVER {
+        return Err(SyscallError::InvalidArgument);
+    }
+    Ok(())
+}
+
+// Store a new semaphore, mutex or event group in the first free slot and return its handle
+fn insert_object<T>(slots: &mut Vec<Option<T>>, max: usize, object: T) -> SyscallResult {
+    let handle = match slots.iter().position(|o| o.is_none()) {
+        Some(handle) => handle,
+        None if slots.len() < max => {
+            slots.push(None);
+            slots.len() - 1
+        },
+        None => return Err(SyscallError::NoMemory),
+    };
+    slots[handle] = Some(object);
+    Ok(handle as u32)
+}
+
+// The object behind a handle that a create syscall returned
+fn object<T>(slots: &mut [Option<T>], handle: u32) -> Result<&mut T, SyscallError> {
+    slots.get_mut(handle as usize).and_then(|o| o.as_mut()).ok_or(SyscallError::InvalidArgument)
+}
+
+// Whether `flags` satisfy the event wait of a process, given its mask and options
+fn event_wait_satisfied(flags: u32, mask: u32, options: u32) -> bool {
+    if options & EVENT_WAIT_ALL != 0 {
+        flags & mask == mask
+    } else {
+        flags & mask != 0
+    }
+}
 
 pub struct Scheduler<'a> {
-    list: LinkedList<'a, Process<'a>>,
+    // One ready queue per priority level. 0 is the highest priority.
+    queues: [LinkedList<'a, Process<'a>>; NUM_PRIORITIES],
+    sleeping: WaitQueue<'a>,
+    // Finished processes on static stacks, which cannot be freed like spawned ones
+    exited: LinkedList<'a, Process<'a>>,
+    // The running process. It is in none of the queues.
+    current: Option<&'a mut ListItem<'a, Process<'a>>>,
+    // Runs when no process is ready
+    idle: Process<'a>,
+    // Output of the write syscall
+    console: Box<dyn Console>,
+    pins: PinTable,
+    button_waiters: WaitQueue<'a>,
+    button_events: VecDeque<ButtonEvent>,
+    // Indexed by the handles that the create syscalls return. Deleted ones leave a free slot.
+    semaphores: Vec<Option<Semaphore<'a>>>,
+    mutexes: Vec<Option<Mutex<'a>>>,
+    // The first one is `abi::BUTTON_EVENT_FLAGS`
+    event_groups: Vec<Option<EventFlags<'a>>>,
+    // Blocked in send or call until the mailbox of the destination has room
+    mail_senders: WaitQueue<'a>,
+    // Blocked in receive or call until a message arrives
+    mail_receivers: WaitQueue<'a>,
+    // Indexed by handle. A slot is free once its owner revokes it or stops.
+    grants: Vec<Option<Grant>>,
+    // Deadlines of the sleeping processes and of the waits with a timeout
+    timers: TimerList<'a>,
+    // Ids and exit codes of the processes that finished for good, the latest
+    // last. Kept after spawned ones are freed, up to `MAX_EXIT_CODES`.
+    exit_codes: VecDeque<(usize, u32)>,
+    // Blocked in join until the process they wait for finishes
+    joiners: WaitQueue<'a>,
+    time_slice: u32,
+    next_id: usize,
 }
 
 impl<'a> Scheduler<'a> {
     pub fn new() -> Self {
+        let mut idle = Process::new(unsafe { &mut IDLE_STACK }, idle_main, NUM_PRIORITIES - 1);
+        idle.set_id(IDLE_ID);
         Scheduler {
-            list: LinkedList::new(),
+            queues: [LinkedList::new(), LinkedList::new(), LinkedList::new(), LinkedList::new()],
+            sleeping: WaitQueue::new(),
+            exited: LinkedList::new(),
+            current: None,
+            idle,
+            console: Box::new(Semihosting::new()),
+            pins: PinTable::new(),
+            button_waiters: WaitQueue::new(),
+            button_events: VecDeque::new(),
+            semaphores: Vec::new(),
+            mutexes: Vec::new(),
+            event_groups: alloc::vec![Some(EventFlags::new(None))],
+            mail_senders: WaitQueue::new(),
+            mail_receivers: WaitQueue::new(),
+            grants: Vec::new(),
+            timers: TimerList::new(),
+            exit_codes: VecDeque::new(),
+            joiners: WaitQueue::new(),
+            time_slice: DEFAULT_TIME_SLICE,
+            next_id: 0,
+        }
+    }
+
+    /// Set the number of SysTick ticks a process may run before it is preempted.
+    pub fn set_time_slice(&mut self, ticks: u32) {
+        self.time_slice = ticks.max(1);
+    }
+
+    /// Add a process to the ready queue of its priority and return its id.
+    pub fn push(&mut self, item: &'a mut ListItem<'a, Process<'a>>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        item.set_id(id);
+        self.make_ready(item);
+        id
+    }
+
+    /// Create a process at runtime with a stack of `stack_size` bytes from the global allocator.
+    /// `entry` receives `arg` in r0. Returns the id of the new process.
+    pub fn spawn(&mut self, entry: ProcessEntry, arg: usize, priority: usize, stack_size: usize) -> Option<usize> {
+        let layout = stack_layout(stack_size)?;
+        // Boxing the item would panic when the heap runs out, so allocate it by hand
+        let item_layout = Layout::new::<ListItem<'a, Process<'a>>>();
+        let item = unsafe { alloc(item_layout) as *mut ListItem<'a, Process<'a>> };
+        if item.is_null() {
+            return None;
+        }
+        let stack = unsafe { alloc(layout) as *mut MaybeUninit<u8> };
+        if stack.is_null() {
+            unsafe {
+                dealloc(item as *mut u8, item_layout);
+            }
+            return None;
+        }
+        let stack = unsafe { slice::from_raw_parts_mut(stack, layout.size()) };
+        let mut process = Process::with_stack(stack, entry, priority);
+        process.set_heap_stack(true);
+        process.set_argument(arg);
+        let item = unsafe {
+            item.write(ListItem::new(process));
+            &mut *item
+        };
+        Some(self.push(item))
+    }
+
+    /// Change the priority of a process in any state.
+    /// Returns false when there is no process with the id.
+    pub fn set_priority(&mut self, id: usize, priority: usize) -> bool {
+        let priority = priority.min(NUM_PRIORITIES - 1);
+        match self.process_mut(id) {
+            Some(process) => process.set_base_priority(priority),
+            None => return false,
+        }
+        self.update_priority(id);
+        true
+    }
+
+    /// Recompute the priority a process runs at from its base priority and
+    /// the waiters of the mutexes it holds. When it changes, pass the change
+    /// on to the owner of the mutex the process waits for, and so on.
+    fn update_priority(&mut self, id: usize) {
+        let mut id = id;
+        loop {
+            let base_priority = match self.process(id) {
+                Some(process) => process.base_priority(),
+                None => return,
+            };
+            let priority = self.mutexes.iter().flatten()
+                .filter(|m| m.owner == Some(id))
+                .flat_map(|m| m.waiters.iter())
+                .map(|p| p.priority())
+                .fold(base_priority, usize::min);
+            if !self.set_effective_priority(id, priority) {
+                return;
+            }
+            let owner = self.mutexes.iter().flatten()
+                .find(|m| m.waiters.iter().any(|p| p.id() == id))
+                .and_then(|m| m.owner);
+            match owner {
+                Some(owner) => id = owner,
+                None => return,
+            }
         }
     }
 
-    pub fn push(&mut self, item: &'a mut ListItem<'a, Process<'a>>) {
-        self.list.push(item);
+    /// Move a process to `priority` wherever it is. Returns false when it already runs at it.
+    fn set_effective_priority(&mut self, id: usize, priority: usize) -> bool {
+        if self.process(id).map_or(true, |p| p.priority() == priority) {
+            return false;
+        }
+        let item = self.queues.iter_mut().find_map(|queue| queue.remove_first(|p| p.id() == id));
+        match item {
+            Some(item) => {
+                item.set_priority(priority);
+                self.make_ready(item);
+            },
+            None => self.process_mut(id).unwrap().set_priority(priority),
+        }
+        true
     }
 
-    fn schedule_next(&mut self) {
-        let current = self.list.pop().unwrap();
-        self.list.push(current);
+    fn process(&self, id: usize) -> Option<&Process<'a>> {
+        let blocked = self.sleeping.iter()
+            .chain(self.button_waiters.iter())
+            .chain(self.semaphores.iter().flatten().flat_map(|s| s.waiters.iter()))
+            .chain(self.mutexes.iter().flatten().flat_map(|m| m.waiters.iter()))
+            .chain(self.event_groups.iter().flatten().flat_map(|e| e.waiters.iter()))
+            .chain(self.mail_senders.iter())
+            .chain(self.mail_receivers.iter())
+            .chain(self.joiners.iter());
+        self.current.iter().map(|p| &***p)
+            .chain(self.queues.iter().flat_map(|queue| queue.iter()))
+            .chain(blocked)
+            .find(|p| p.id() == id)
+    }
+
+    fn process_mut(&mut self, id: usize) -> Option<&mut Process<'a>> {
+        let blocked = self.sleeping.iter_mut()
+            .chain(self.button_waiters.iter_mut())
+            .chain(self.semaphores.iter_mut().flatten().flat_map(|s| s.waiters.iter_mut()))
+            .chain(self.mutexes.iter_mut().flatten().flat_map(|m| m.waiters.iter_mut()))
+            .chain(self.event_groups.iter_mut().flatten().flat_map(|e| e.waiters.iter_mut()))
+            .chain(self.mail_senders.iter_mut())
+            .chain(self.mail_receivers.iter_mut())
+            .chain(self.joiners.iter_mut());
+        self.current.iter_mut().map(|p| &mut ***p)
+            .chain(self.queues.iter_mut().flat_map(|queue| queue.iter_mut()))
+            .chain(blocked)
+            .find(|p| p.id() == id)
+    }
+
+    /// Hand a mutex to its highest-priority waiter, or leave it free.
+    fn release_mutex(&mut self, handle: usize) {
+        let mutex = self.mutexes[handle].as_mut().unwrap();
+        let previous = mutex.owner.take();
+        if let Some(item) = mutex.waiters.wake_highest() {
+            let id = item.id();
+            mutex.owner = Some(id);
+            item.set_syscall_result(Ok(0));
+            self.make_ready(item);
+            self.update_priority(id);
+        }
+        if let Some(previous) = previous {
+            self.update_priority(previous);
+        }
+    }
+
+    /// Exit code of a process that has finished, or None while it is still alive.
+    /// Only the last `MAX_EXIT_CODES` are kept.
+    fn exit_code(&self, id: usize) -> Option<u32> {
+        self.exit_codes.iter().find(|(p, _)| *p == id).map(|(_, code)| *code)
+    }
+
+    fn exit(&mut self, item: &'a mut ListItem<'a, Process<'a>>, code: u32) {
+        hprintln!("Process {} exited with {} (peak stack {}/{} bytes)",
+            item.id(), code, item.peak_stack_usage(), item.stack_size()).unwrap();
+        self.terminate(item, ProcessState::Exited, code);
+    }
+
+    /// Remove a process that cannot continue. The other processes keep running.
+    fn kill(&mut self, item: &'a mut ListItem<'a, Process<'a>>, reason: &str) {
+        let pc = if item.has_valid_frame() {
+            Some(item.get_context_frame().return_addr)
+        } else {
+            None
+        };
+        hprintln!("Process {} killed: {} (pc {:X?}, peak stack {}/{} bytes)",
+            item.id(), reason, pc, item.peak_stack_usage(), item.stack_size()).unwrap();
+        self.terminate(item, ProcessState::Faulted, EXIT_KILLED);
+    }
+
+    /// Delete the semaphores, mutexes and event groups that a stopped process created.
+    /// Their waiters fail with `InvalidArgument`, like any later use of the handles.
+    fn delete_sync_objects(&mut self, id: usize) {
+        for handle in 0..self.semaphores.len() {
+            if self.semaphores[handle].as_ref().map_or(false, |s| s.creator == id) {
+                let semaphore = self.semaphores[handle].take().unwrap();
+                self.fail_waiters(semaphore.waiters);
+            }
+        }
+        for handle in 0..self.mutexes.len() {
+            if self.mutexes[handle].as_ref().map_or(false, |m| m.creator == id) {
+                let mutex = self.mutexes[handle].take().unwrap();
+                self.fail_waiters(mutex.waiters);
+                // The owner no longer inherits the priority of the waiters
+                if let Some(owner) = mutex.owner {
+                    self.update_priority(owner);
+                }
+            }
+        }
+        for handle in 0..self.event_groups.len() {
+            if self.event_groups[handle].as_ref().map_or(false, |e| e.creator == Some(id)) {
+                let group = self.event_groups[handle].take().unwrap();
+                self.fail_waiters(group.waiters);
+                // A later group with the handle starts without them
+                sync::take_isr_flags(handle);
+            }
+        }
+    }
+
+    fn fail_waiters(&mut self, mut waiters: WaitQueue<'a>) {
+        while let Some(item) = waiters.wake_one() {
+            item.set_syscall_result(Err(SyscallError::InvalidArgument));
+            self.make_ready(item);
+        }
+    }
+
+    /// Supervise a process that has stopped: restart it as its policy
+    /// says, or keep it with the exited ones.
+    fn terminate(&mut self, item: &'a mut ListItem<'a, Process<'a>>, state: ProcessState, code: u32) {
+        self.pins.release_all(PinOwner::Process(item.id()));
+        self.abort_messages(item.id());
+        self.cancel_timeout(item.id());
+        self.revoke_grants(item);
+        for handle in 0..self.mutexes.len() {
+            if self.mutexes[handle].as_ref().map_or(false, |m| m.owner == Some(item.id())) {
+                self.release_mutex(handle);
+            }
+        }
+        self.delete_sync_objects(item.id());
+        let delay = match item.restart_policy().restart_delay(item.restarts()) {
+            Some(delay) => delay,
+            None => {
+                self.remove(item, state, code);
+                return;
+            },
+        };
+        item.restart();
+        hprintln!("Process {} restarts in {}ms ({} restarts)", item.id(), delay, item.restarts()).unwrap();
+        if delay == 0 {
+            self.make_ready(item);
+        } else {
+            let wakeup_time = systick::now().wrapping_add(systick::ms_to_ticks(delay));
+            self.sleep(item, wakeup_time);
+        }
+    }
+
+    fn remove(&mut self, item: &'a mut ListItem<'a, Process<'a>>, state: ProcessState, code: u32) {
+        item.set_state(state);
+        if self.exit_codes.len() == MAX_EXIT_CODES {
+            self.exit_codes.pop_front();
+        }
+        self.exit_codes.push_back((item.id(), code));
+        while let Some(joiner) = self.joiners.wake_first(|p| p.syscall_args()[0] as usize == item.id()) {
+            joiner.set_syscall_result(Ok(code));
+            self.make_ready(joiner);
+        }
+        if !item.has_heap_stack() {
+            self.exited.push(item);
+            return;
+        }
+        // `spawn` allocated both the stack and the item
+        unsafe {
+            dealloc(item.stack_bottom() as *mut u8, stack_layout(item.stack_size()).unwrap());
+            drop(Box::from_raw(item as *mut ListItem<'a, Process<'a>>));
+        }
+    }
+
+    /// Queue a process to run. A process that was blocked no longer times out.
+    fn make_ready(&mut self, item: &'a mut ListItem<'a, Process<'a>>) {
+        self.cancel_timeout(item.id());
+        item.set_state(ProcessState::Ready);
+        let priority = item.priority();
+        self.queues[priority].push(item);
+    }
+
+    /// Take the running process off the CPU until `wakeup_time`.
+    fn sleep(&mut self, item: &'a mut ListItem<'a, Process<'a>>, wakeup_time: u32) {
+        let timer = item.timer();
+        self.sleeping.block(item);
+        self.timers.add(timer, wakeup_time);
+        self.update_next_wakeup();
+    }
+
+    /// Fail the wait that the owner of `timer` just blocked in with `TimedOut` after `timeout` ticks.
+    fn start_timeout(&mut self, timer: &'a mut ListItem<'a, Timer>, timeout: u32) {
+        if timeout != WAIT_FOREVER {
+            self.timers.add(timer, systick::now().wrapping_add(timeout));
+            self.update_next_wakeup();
+        }
+    }
+
+    fn cancel_timeout(&mut self, id: usize) {
+        if self.timers.cancel(id) {
+            self.update_next_wakeup();
+        }
+    }
+
+    /// Wake the sleepers and fail the waits whose deadline has passed.
+    fn expire_timers(&mut self) {
+        let now = systick::now();
+        let mut expired = false;
+        while let Some(id) = self.timers.expire(now) {
+            expired = true;
+            if let Some(item) = self.sleeping.wake_first(|p| p.id() == id) {
+                self.make_ready(item);
+            } else if let Some(item) = self.unblock(id) {
+                item.set_syscall_result(Err(SyscallError::TimedOut));
+                self.make_ready(item);
+            }
+        }
+        if expired {
+            self.update_next_wakeup();
+        }
+    }
+
+    // Take a process out of the wait queue it is blocked in
+    fn unblock(&mut self, id: usize) -> Option<&'a mut ListItem<'a, Process<'a>>> {
+        let waiting = |p: &Process| p.id() == id;
+        let blocked_on_mutex = |m: &Option<Mutex>| m.as_ref().map_or(false, |m| m.waiters.iter().any(waiting));
+        if let Some(handle) = self.mutexes.iter().position(blocked_on_mutex) {
+            let mutex = self.mutexes[handle].as_mut().unwrap();
+            let item = mutex.waiters.wake_first(waiting)?;
+            // The owner no longer inherits the priority of the process
+            if let Some(owner) = mutex.owner {
+                self.update_priority(owner);
+            }
+            return Some(item);
+        }
+        self.button_waiters.wake_first(waiting)
+            .or_else(|| self.semaphores.iter_mut().flatten().find_map(|s| s.waiters.wake_first(waiting)))
+            .or_else(|| self.event_groups.iter_mut().flatten().find_map(|e| e.waiters.wake_first(waiting)))
+            .or_else(|| self.mail_senders.wake_first(waiting))
+            .or_else(|| self.mail_receivers.wake_first(waiting))
+            .or_else(|| self.joiners.wake_first(waiting))
+    }
+
+    fn update_next_wakeup(&mut self) {
+        let next = self.timers.next_deadline();
+        if let Some(deadline) = next {
+            NEXT_DEADLINE.store(deadline, Ordering::Relaxed);
+        }
+        HAS_TIMERS.store(next.is_some(), Ordering::Relaxed);
+    }
+
+    /// Hand the events from SysTick to the processes waiting for them.
+    fn deliver_button_events(&mut self) {
+        while let Some(event) = button_service::pop() {
+            let bit = 1 << event.button;
+            match self.button_waiters.wake_first(|p| p.syscall_args()[0] & bit != 0) {
+                Some(item) => {
+                    item.set_syscall_result(Ok(event.to_u32()));
+                    self.make_ready(item);
+                },
+                None => {
+                    if self.button_events.len() == MAX_PENDING_BUTTON_EVENTS {
+                        self.button_events.pop_front();
+                    }
+                    self.button_events.push_back(event);
+                },
+            }
+        }
+    }
+
+    /// Merge the flags that interrupt handlers have set into their groups.
+    fn deliver_isr_flags(&mut self) {
+        for handle in 0..self.event_groups.len() {
+            let bits = sync::take_isr_flags(handle);
+            if bits != 0 && self.event_groups[handle].is_some() {
+                self.set_event_flags(handle, bits);
+            }
+        }
+    }
+
+    /// Set `bits` in an event group and wake the waiters that they satisfy, in order.
+    fn set_event_flags(&mut self, handle: usize, bits: u32) {
+        self.event_groups[handle].as_mut().unwrap().flags |= bits;
+        loop {
+            let group = self.event_groups[handle].as_mut().unwrap();
+            let flags = group.flags;
+            let item = group.waiters.wake_first(|p| {
+                let args = p.syscall_args();
+                event_wait_satisfied(flags, args[1], args[2])
+            });
+            let item = match item {
+                Some(item) => item,
+                None => break,
+            };
+            if item.syscall_args()[2] & EVENT_CLEAR != 0 {
+                group.flags &= !item.syscall_args()[1];
+            }
+            item.set_syscall_result(Ok(flags));
+            self.make_ready(item);
+        }
+    }
+
+    /// Deliver a message from the running process to `dest`, or block the
+    /// process until the mailbox has room. Returns false when it blocked.
+    fn post_message(&mut self, dest: usize, buf: usize, timeout: u32) -> Result<bool, SyscallError> {
+        let current = self.current.as_ref().unwrap();
+        if dest == current.id() {
+            return Err(SyscallError::Deadlock);
+        }
+        let mut message = Message { sender: current.id(), data: [0; MESSAGE_SIZE] };
+        uaccess::copy_from_user(current, &mut message.data, buf)?;
+        if self.deliver(dest, message)? {
+            return Ok(true);
+        }
+        if timeout == 0 {
+            return Err(SyscallError::TimedOut);
+        }
+        let current = self.current.take().unwrap();
+        let timer = current.timer();
+        self.mail_senders.block(current);
+        self.start_timeout(timer, timeout);
+        Ok(false)
+    }
+
+    /// Hand a message straight to `dest` if it is waiting for it, or put it
+    /// in its mailbox. Returns false when the mailbox is full.
+    fn deliver(&mut self, dest: usize, message: Message) -> Result<bool, SyscallError> {
+        if let Some(item) = self.mail_receivers.wake_first(|p| p.id() == dest && accepts(p, message.sender)) {
+            let (buf, _) = receive_args(item);
+            let result = uaccess::copy_to_user(item, buf, &message.data);
+            item.set_syscall_result(result.map(|_| message.sender as u32));
+            self.make_ready(item);
+            // The message stays in the mailbox when the buffer is bad
+            if result.is_ok() {
+                return Ok(true);
+            }
+        }
+        let process = self.process_mut(dest).ok_or(SyscallError::NoSuchProcess)?;
+        Ok(process.mailbox_mut().push(message))
+    }
+
+    /// Take the message that `process`, in receive or call, waits for and copy
+    /// it into its buffer. It comes from the mailbox or, when none there
+    /// matches, straight from a blocked sender. Returns None when there is none yet.
+    fn receive(&mut self, process: &mut Process<'a>) -> Option<SyscallResult> {
+        let (buf, from) = receive_args(process);
+        // Checked first so that a bad buffer does not lose the message
+        if !process.can_access(buf, MESSAGE_SIZE, Access::ReadWrite) {
+            return Some(Err(SyscallError::BadAddress));
+        }
+        let sender = if from == ANY_SENDER { None } else { Some(from as usize) };
+        let message = match process.mailbox_mut().take(sender) {
+            Some(message) => {
+                // Make room for the first sender blocked on the mailbox
+                if let Some(next) = self.take_from_sender(process.id(), |_| true) {
+                    process.mailbox_mut().push(next);
+                }
+                message
+            },
+            None => self.take_from_sender(process.id(), |id| sender.map_or(true, |s| s == id))?,
+        };
+        Some(uaccess::copy_to_user(process, buf, &message.data).map(|_| message.sender as u32))
+    }
+
+    /// Take the message of the first sender blocked on the mailbox of `id`
+    /// whose id matches `f`. The sender goes on as if the mailbox had taken it.
+    fn take_from_sender<F: FnMut(usize) -> bool>(&mut self, id: usize, mut f: F) -> Option<Message> {
+        loop {
+            let sender = self.mail_senders.wake_first(|p| p.syscall_args()[0] as usize == id && f(p.id()))?;
+            let mut message = Message { sender: sender.id(), data: [0; MESSAGE_SIZE] };
+            match uaccess::copy_from_user(sender, &mut message.data, sender.syscall_args()[1] as usize) {
+                Ok(()) => {
+                    self.message_sent(sender);
+                    return Some(message);
+                },
+                Err(error) => {
+                    sender.set_syscall_result(Err(error));
+                    self.make_ready(sender);
+                },
+            }
+        }
+    }
+
+    /// The message of a blocked sender is delivered. A send returns, and a call
+    /// goes on to wait for the reply.
+    fn message_sent(&mut self, item: &'a mut ListItem<'a, Process<'a>>) {
+        if item.syscall_number() != Syscall::Call as u32 {
+            item.set_syscall_result(Ok(0));
+            self.make_ready(item);
+            return;
+        }
+        match self.receive(item) {
+            Some(result) => {
+                item.set_syscall_result(result);
+                self.make_ready(item);
+            },
+            None => self.mail_receivers.block(item),
+        }
+    }
+
+    /// Fail the sends to `id` and the calls waiting for its reply, when it terminates.
+    fn abort_messages(&mut self, id: usize) {
+        while let Some(item) = self.mail_senders.wake_first(|p| p.syscall_args()[0] as usize == id) {
+            item.set_syscall_result(Err(SyscallError::NoSuchProcess));
+            self.make_ready(item);
+        }
+        while let Some(item) = self.mail_receivers.wake_first(|p| receive_args(p).1 == id as u32) {
+            item.set_syscall_result(Err(SyscallError::NoSuchProcess));
+            self.make_ready(item);
+        }
+    }
+
+    /// Take back the grants the process gave or received.
+    fn revoke_grants(&mut self, item: &mut Process<'a>) {
+        let id = item.id();
+        for handle in 0..self.grants.len() {
+            match self.grants[handle].as_mut() {
+                Some(grant) if grant.grantee == id && !grant.revoked => {
+                    item.revoke(grant.region);
+                    grant.revoked = true;
+                },
+                Some(grant) if grant.owner == id => self.revoke_grant(handle),
+                _ => {},
+            }
+        }
+    }
+
+    fn revoke_grant(&mut self, handle: usize) {
+        match self.grants[handle].take() {
+            // The grantee already lost the region when it stopped
+            Some(grant) if !grant.revoked => {
+                if let Some(grantee) = self.process_mut(grant.grantee) {
+                    grantee.revoke(grant.region);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn highest_ready_priority(&self) -> Option<usize> {
+        self.queues.iter().position(|queue| !queue.is_empty())
+    }
+
+    /// Make the highest-priority ready process the current one.
+    /// The current process keeps the CPU unless a higher priority is ready.
+    fn dispatch(&mut self) {
+        let highest = match self.highest_ready_priority() {
+            Some(priority) => priority,
+            None => return,
+        };
+        if let Some(current) = self.current.take() {
+            if current.priority() <= highest {
+                self.current = Some(current);
+                return;
+            }
+            // Preempted by a higher priority, so it stays first in line
+            current.set_state(ProcessState::Ready);
+            self.queues[current.priority()].push_front(current);
+        }
+        let next = self.queues[highest].pop().unwrap();
+        next.set_state(ProcessState::Running);
+        self.current = Some(next);
+        TIME_SLICE_REMAINING.store(self.time_slice, Ordering::Relaxed);
+    }
+
+    fn handle_syscall(&mut self, devices: &Devices) {
+        let current = self.current.as_mut().unwrap();
+        let number = current.get_context_frame().r0;
+        let args = current.syscall_args();
+        let result = match Syscall::from_u32(number) {
+            Some(syscall) => Self::SYSCALL_HANDLERS[syscall as usize](self, devices, args),
+            None => Err(SyscallError::NoSuchSyscall),
+        };
+        // A handler that takes the caller off the CPU sets its result itself, once there is one
+        if let Some(current) = self.current.as_mut() {
+            current.set_syscall_result(result);
+        }
+    }
+
+    // Indexed by the syscall number
+    const SYSCALL_HANDLERS: [SyscallHandler<'a>; Syscall::COUNT] = [
+        Self::sys_yield,
+        Self::sys_set_led,
+        Self::sys_get_button,
+        Self::sys_set_priority,
+        Self::sys_sleep,
+        Self::sys_get_time,
+        Self::sys_exit,
+        Self::sys_spawn,
+        Self::sys_stack_usage,
+        Self::sys_write,
+        Self::sys_gpio_claim,
+        Self::sys_gpio_release,
+        Self::sys_gpio_configure,
+        Self::sys_gpio_write,
+        Self::sys_gpio_read,
+        Self::sys_gpio_toggle,
+        Self::sys_wait_button_event,
+        Self::sys_sem_create,
+        Self::sys_sem_wait,
+        Self::sys_sem_post,
+        Self::sys_mutex_create,
+        Self::sys_mutex_lock,
+        Self::sys_mutex_unlock,
+        Self::sys_send,
+        Self::sys_receive,
+        Self::sys_call,
+        Self::sys_grant,
+        Self::sys_revoke,
+        Self::sys_event_create,
+        Self::sys_event_set,
+        Self::sys_event_clear,
+        Self::sys_event_wait,
+        Self::sys_join,
+    ];
+
+    fn sys_yield(&mut self, _devices: &Devices, _args: [u32; 4]) -> SyscallResult {
+        let current = self.current.take().unwrap();
+        current.set_syscall_result(Ok(0));
+        self.make_ready(current);
+        Ok(0)
+    }
+
+    fn sys_set_led(&mut self, devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        if args[0] > 0 {
+            devices.led.set();
+        } else {
+            devices.led.clear();
+        }
+        Ok(0)
+    }
+
+    fn sys_get_button(&mut self, _devices: &Devices, _args: [u32; 4]) -> SyscallResult {
+        Ok(button_service::is_pressed(0) as u32)
+    }
+
+    fn sys_set_priority(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let priority = args[0] as usize;
+        if priority >= NUM_PRIORITIES {
+            return Err(SyscallError::InvalidArgument);
+        }
+        let id = self.current.as_ref().unwrap().id();
+        self.set_priority(id, priority);
+        Ok(0)
+    }
+
+    fn sys_sleep(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let current = self.current.take().unwrap();
+        let wakeup_time = systick::now().wrapping_add(systick::ms_to_ticks(args[0]));
+        current.set_syscall_result(Ok(0));
+        self.sleep(current, wakeup_time);
+        Ok(0)
+    }
+
+    fn sys_get_time(&mut self, _devices: &Devices, _args: [u32; 4]) -> SyscallResult {
+        Ok(systick::now())
+    }
+
+    fn sys_exit(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let current = self.current.take().unwrap();
+        self.exit(current, args[0]);
+        Ok(0)
+    }
+
+    fn sys_spawn(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let [entry, arg, priority, stack_size] = args;
+        if entry == 0 || priority as usize >= NUM_PRIORITIES {
+            return Err(SyscallError::InvalidArgument);
+        }
+        let entry: ProcessEntry = unsafe { transmute(entry as usize) };
+        match self.spawn(entry, arg as usize, priority as usize, stack_size as usize) {
+            Some(id) => Ok(id as u32),
+            None => Err(SyscallError::NoMemory),
+        }
+    }
+
+    fn sys_stack_usage(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let process = if args[0] == CURRENT_PROCESS {
+            self.current.as_deref().map(|p| &**p)
+        } else {
+            self.process(args[0] as usize)
+        };
+        process.map(|p| p.peak_stack_usage() as u32).ok_or(SyscallError::NoSuchProcess)
+    }
+
+    fn sys_write(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let [fd, buf, len, _] = args;
+        if fd != abi::STDOUT && fd != abi::STDERR {
+            return Err(SyscallError::BadFileDescriptor);
+        }
+        let current = self.current.as_ref().unwrap();
+        // Check the whole buffer first, so a bad one prints nothing
+        if !current.can_access(buf as usize, len as usize, Access::ReadOnly) {
+            return Err(SyscallError::BadAddress);
+        }
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut written = 0;
+        while written < len as usize {
+            let n = CHUNK_SIZE.min(len as usize - written);
+            uaccess::copy_from_user(current, &mut chunk[..n], buf as usize + written)?;
+            self.console.write(&chunk[..n]);
+            written += n;
+        }
+        Ok(len)
+    }
+
+    fn caller(&self) -> PinOwner {
+        PinOwner::Process(self.current.as_ref().unwrap().id())
+    }
+
+    fn sys_gpio_claim(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let owner = self.caller();
+        self.pins.claim(args[0] as usize, args[1] as usize, owner)?;
+        Ok(0)
+    }
+
+    fn sys_gpio_release(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let owner = self.caller();
+        self.pins.release(args[0] as usize, args[1] as usize, owner)?;
+        Ok(0)
+    }
+
+    fn sys_gpio_configure(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let pin = self.pins.get(args[0] as usize, args[1] as usize, self.caller())?;
+        let mode = PinMode::from_u32(args[2]).ok_or(SyscallError::InvalidArgument)?;
+        gpio::configure(&pin, mode);
+        Ok(0)
+    }
+
+    fn sys_gpio_write(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let pin = self.pins.get(args[0] as usize, args[1] as usize, self.caller())?;
+        if args[2] != 0 {
+            pin.set_out();
+        } else {
+            pin.clear_out();
+        }
+        Ok(0)
+    }
+
+    fn sys_gpio_read(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let pin = self.pins.get(args[0] as usize, args[1] as usize, self.caller())?;
+        Ok(pin.get_in() as u32)
+    }
+
+    fn sys_gpio_toggle(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let pin = self.pins.get(args[0] as usize, args[1] as usize, self.caller())?;
+        pin.toggle_out();
+        Ok(0)
+    }
+
+    fn sys_wait_button_event(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        check_timeout(args[1])?;
+        let mask = args[0];
+        if mask == 0 || mask >> NUM_BUTTONS != 0 {
+            return Err(SyscallError::InvalidArgument);
+        }
+        if let Some(i) = self.button_events.iter().position(|e| mask & (1 << e.button) != 0) {
+            return Ok(self.button_events.remove(i).unwrap().to_u32());
+        }
+        if args[1] == 0 {
+            return Err(SyscallError::TimedOut);
+        }
+        let current = self.current.take().unwrap();
+        let timer = current.timer();
+        self.button_waiters.block(current);
+        self.start_timeout(timer, args[1]);
+        Ok(0)
+    }
+
+    fn sys_sem_create(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let creator = self.current.as_ref().unwrap().id();
+        insert_object(&mut self.semaphores, MAX_SEMAPHORES, Semaphore::new(args[0], creator))
+    }
+
+    fn sys_sem_wait(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        check_timeout(args[1])?;
+        let semaphore = object(&mut self.semaphores, args[0])?;
+        if semaphore.count > 0 {
+            semaphore.count -= 1;
+            return Ok(0);
+        }
+        if args[1] == 0 {
+            return Err(SyscallError::TimedOut);
+        }
+        let current = self.current.take().unwrap();
+        let timer = current.timer();
+        semaphore.waiters.block(current);
+        self.start_timeout(timer, args[1]);
+        Ok(0)
+    }
+
+    fn sys_sem_post(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let semaphore = object(&mut self.semaphores, args[0])?;
+        match semaphore.waiters.wake_highest() {
+            Some(item) => {
+                item.set_syscall_result(Ok(0));
+                self.make_ready(item);
+            },
+            None => semaphore.count = semaphore.count.checked_add(1).ok_or(SyscallError::InvalidArgument)?,
+        }
+        Ok(0)
+    }
+
+    fn sys_mutex_create(&mut self, _devices: &Devices, _args: [u32; 4]) -> SyscallResult {
+        let creator = self.current.as_ref().unwrap().id();
+        insert_object(&mut self.mutexes, MAX_MUTEXES, Mutex::new(creator))
+    }
+
+    fn sys_mutex_lock(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        check_timeout(args[1])?;
+        let id = self.current.as_ref().unwrap().id();
+        let mutex = object(&mut self.mutexes, args[0])?;
+        match mutex.owner {
+            None => {
+                mutex.owner = Some(id);
+                Ok(0)
+            },
+            Some(owner) if owner == id => Err(SyscallError::Deadlock),
+            Some(_) if args[1] == 0 => Err(SyscallError::TimedOut),
+            Some(owner) => {
+                let current = self.current.take().unwrap();
+                let timer = current.timer();
+                mutex.waiters.block(current);
+                // The owner now runs at least at the priority of the caller
+                self.update_priority(owner);
+                self.start_timeout(timer, args[1]);
+                Ok(0)
+            },
+        }
+    }
+
+    fn sys_mutex_unlock(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let id = self.current.as_ref().unwrap().id();
+        let handle = args[0] as usize;
+        match self.mutexes.get(handle).and_then(|m| m.as_ref()) {
+            Some(mutex) if mutex.owner == Some(id) => {
+                self.release_mutex(handle);
+                Ok(0)
+            },
+            Some(_) => Err(SyscallError::PermissionDenied),
+            None => Err(SyscallError::InvalidArgument),
+        }
+    }
+
+    fn sys_send(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        check_timeout(args[2])?;
+        self.post_message(args[0] as usize, args[1] as usize, args[2])?;
+        Ok(0)
+    }
+
+    fn sys_receive(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        check_timeout(args[2])?;
+        self.receive_current(args[2])
+    }
+
+    fn sys_call(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        check_timeout(args[3])?;
+        let current = self.current.as_ref().unwrap();
+        if !current.can_access(args[2] as usize, MESSAGE_SIZE, Access::ReadWrite) {
+            return Err(SyscallError::BadAddress);
+        }
+        if !self.post_message(args[0] as usize, args[1] as usize, args[3])? {
+            return Ok(0);
+        }
+        self.receive_current(args[3])
+    }
+
+    fn sys_grant(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let (dest, start, size) = (args[0] as usize, args[1] as usize, args[2] as usize);
+        let access = match args[3] {
+            0 => Access::ReadOnly,
+            1 => Access::ReadWrite,
+            _ => return Err(SyscallError::InvalidArgument),
+        };
+        let region = Region::new(start, size, access).ok_or(SyscallError::InvalidArgument)?;
+        let owner = self.current.as_ref().unwrap().id();
+        if !self.current.as_ref().unwrap().owns(start, size) {
+            return Err(SyscallError::BadAddress);
+        }
+        if dest == owner {
+            return Err(SyscallError::InvalidArgument);
+        }
+        let handle = match self.grants.iter().position(|g| g.is_none()) {
+            Some(handle) => handle,
+            None if self.grants.len() < MAX_GRANTS => {
+                self.grants.push(None);
+                self.grants.len() - 1
+            },
+            None => return Err(SyscallError::NoMemory),
+        };
+        let grantee = self.process_mut(dest).ok_or(SyscallError::NoSuchProcess)?;
+        if !grantee.grant(region) {
+            return Err(SyscallError::NoMemory);
+        }
+        self.grants[handle] = Some(Grant { owner, grantee: dest, region, revoked: false });
+        Ok(handle as u32)
+    }
+
+    fn sys_revoke(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let id = self.current.as_ref().unwrap().id();
+        let handle = args[0] as usize;
+        match self.grants.get(handle).copied().flatten() {
+            Some(grant) if grant.owner == id => {
+                self.revoke_grant(handle);
+                Ok(0)
+            },
+            Some(_) => Err(SyscallError::PermissionDenied),
+            None => Err(SyscallError::InvalidArgument),
+        }
+    }
+
+    fn sys_event_create(&mut self, _devices: &Devices, _args: [u32; 4]) -> SyscallResult {
+        let creator = self.current.as_ref().unwrap().id();
+        insert_object(&mut self.event_groups, MAX_EVENT_GROUPS, EventFlags::new(Some(creator)))
+    }
+
+    fn sys_event_set(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        object(&mut self.event_groups, args[0])?;
+        // Only the kernel reports button presses
+        if args[0] == BUTTON_EVENT_FLAGS {
+            return Err(SyscallError::PermissionDenied);
+        }
+        self.set_event_flags(args[0] as usize, args[1]);
+        Ok(0)
+    }
+
+    fn sys_event_clear(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        if args[0] == BUTTON_EVENT_FLAGS {
+            return Err(SyscallError::PermissionDenied);
+        }
+        let group = object(&mut self.event_groups, args[0])?;
+        let flags = group.flags;
+        group.flags &= !args[1];
+        Ok(flags)
+    }
+
+    fn sys_event_wait(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        let (mask, options, timeout) = (args[1], args[2], args[3]);
+        check_timeout(timeout)?;
+        if mask == 0 || options & !(EVENT_WAIT_ALL | EVENT_CLEAR) != 0 {
+            return Err(SyscallError::InvalidArgument);
+        }
+        let group = object(&mut self.event_groups, args[0])?;
+        let flags = group.flags;
+        if event_wait_satisfied(flags, mask, options) {
+            if options & EVENT_CLEAR != 0 {
+                group.flags &= !mask;
+            }
+            return Ok(flags);
+        }
+        if timeout == 0 {
+            return Err(SyscallError::TimedOut);
+        }
+        let current = self.current.take().unwrap();
+        let timer = current.timer();
+        group.waiters.block(current);
+        self.start_timeout(timer, timeout);
+        Ok(0)
+    }
+
+    fn sys_join(&mut self, _devices: &Devices, args: [u32; 4]) -> SyscallResult {
+        check_timeout(args[1])?;
+        let id = args[0] as usize;
+        if id == self.current.as_ref().unwrap().id() {
+            return Err(SyscallError::Deadlock);
+        }
+        if let Some(code) = self.exit_code(id) {
+            return Ok(code);
+        }
+        if self.process(id).is_none() {
+            return Err(SyscallError::NoSuchProcess);
+        }
+        if args[1] == 0 {
+            return Err(SyscallError::TimedOut);
+        }
+        let current = self.current.take().unwrap();
+        let timer = current.timer();
+        self.joiners.block(current);
+        self.start_timeout(timer, args[1]);
+        Ok(0)
+    }
+
+    // Receive for the running process, blocking it while nothing has arrived
+    fn receive_current(&mut self, timeout: u32) -> SyscallResult {
+        let current = self.current.take().unwrap();
+        match self.receive(current) {
+            Some(result) => {
+                self.current = Some(current);
+                result
+            },
+            None if timeout == 0 => {
+                self.current = Some(current);
+                Err(SyscallError::TimedOut)
+            },
+            None => {
+                let timer = current.timer();
+                self.mail_receivers.block(current);
+                self.start_timeout(timer, timeout);
+                Ok(0)
+            },
+        }
     }
 
     pub fn exec(&mut self) -> ! {
         let porta = Port::<PortA>::new();
-        let led = LED::new(&porta.pin15);
         let portc = Port::<PortC>::new();
-        let button1 = Button1::new(&portc.pin26);
-        led.init();
-        button1.init();
+        let devices = Devices {
+            led: LED::new(&porta.pin15),
+        };
+        devices.led.init();
+        button_service::init();
+        self.pins.reserve(&porta.pin15);
+        self.pins.reserve(&portc.pin26);
+        self.pins.reserve(&portc.pin27);
+        self.pins.reserve(&portc.pin28);
+        unsafe {
+            // PendSV must not preempt other handlers while it switches contexts
+            write_volatile(SHPR3_PENDSV_ADDR as *mut u8, LOWEST_PRIORITY);
+        }
         loop {
-            let current = self.list.head_mut();
-            if current.is_none() {
-                unimplemented!();
-            }
-            let mut should_schecule_next = false;
-            current.map(|p| {
-                p.exec();
-                let context_frame = p.get_context_frame();
-                match context_frame.r0 {
-                    // yield
-                    0 => {
-                        should_schecule_next = true;
-                    },
-                    //set_led
-                    1 => {
-                        if context_frame.r1 > 0 {
-                            led.set();
-                        } else {
-                            led.clear();
+            self.expire_timers();
+            self.deliver_button_events();
+            self.deliver_isr_flags();
+            self.dispatch();
+            let next = match self.current.as_mut() {
+                Some(current) => &mut ***current,
+                None => &mut self.idle,
+            };
+            mpu::configure(next.regions());
+            next.exec();
+            let reason = SWITCH_REASON.swap(0, Ordering::Relaxed);
+            if self.current.is_none() {
+                if reason & SWITCH_FAULT != 0 {
+                    panic!("idle process faulted");
+                }
+                continue;
+            }
+            if reason & SWITCH_FAULT != 0 {
+                let current = self.current.take().unwrap();
+                match fault::take() {
+                    Some(fault) => {
+                        hprintln!("Fault in process {}", current.id()).unwrap();
+                        fault.print();
+                        if fault.has_stacked_frame() && current.has_valid_frame() {
+                            fault::print_frame(current.get_context_frame());
                         }
+                        self.kill(current, &format!("{:?}", fault.kind));
                     },
-                    // get_button
-                    2 => {
-                        context_frame.r0 = button1.is_pushed() as u32;
-                    },
-                    _ => {}
+                    None => self.kill(current, "fault"),
+                }
+                continue;
+            }
+            if !self.current.as_ref().unwrap().is_stack_intact() {
+                let current = self.current.take().unwrap();
+                self.kill(current, "stack overflow");
+                continue;
+            }
+            if reason & SWITCH_SYSCALL != 0 {
+                self.handle_syscall(&devices);
+            }
+            if reason & SWITCH_PREEMPT != 0 {
+                if let Some(current) = self.current.take() {
+                    self.make_ready(current);
                 }
-            });
-            if should_schecule_next {
-                self.schedule_next();
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_timeout, event_wait_satisfied};
+    use userland::abi::{EVENT_CLEAR, EVENT_WAIT_ALL, MAX_TIMEOUT, WAIT_FOREVER};
+
+    #[test]
+    fn test_event_wait_any() {
+        assert!(event_wait_satisfied(0b0100, 0b0110, 0));
+        assert!(event_wait_satisfied(0b0100, 0b0110, EVENT_CLEAR));
+        assert!(!event_wait_satisfied(0b1001, 0b0110, 0));
+        assert!(!event_wait_satisfied(0, u32::MAX, 0));
+    }
+
+    #[test]
+    fn test_event_wait_all() {
+        assert!(event_wait_satisfied(0b1110, 0b0110, EVENT_WAIT_ALL));
+        assert!(!event_wait_satisfied(0b0100, 0b0110, EVENT_WAIT_ALL | EVENT_CLEAR));
+        assert!(event_wait_satisfied(u32::MAX, u32::MAX, EVENT_WAIT_ALL));
+    }
+
+    #[test]
+    fn test_check_timeout() {
+        assert!(check_timeout(0).is_ok());
+        assert!(check_timeout(MAX_TIMEOUT).is_ok());
+        assert!(check_timeout(WAIT_FOREVER).is_ok());
+        assert!(check_timeout(MAX_TIMEOUT + 1).is_err());
+        assert!(check_timeout(WAIT_FOREVER - 1).is_err());
+    }
+}
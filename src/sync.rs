@@ -0,0 +1,69 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::scheduler;
+use crate::wait_queue::WaitQueue;
+
+pub const MAX_EVENT_GROUPS: usize = 16;
+
+// Flags that interrupt handlers set, until the kernel merges them into the groups
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_FLAGS: AtomicU32 = AtomicU32::new(0);
+static ISR_FLAGS: [AtomicU32; MAX_EVENT_GROUPS] = [NO_FLAGS; MAX_EVENT_GROUPS];
+
+/// Counting semaphore that processes wait on through syscalls.
+/// Any process may wait on or post to it, not only its creator.
+pub struct Semaphore<'a> {
+    pub count: u32,
+    pub waiters: WaitQueue<'a>,
+    // The semaphore is deleted when this process stops
+    pub creator: usize,
+}
+
+impl<'a> Semaphore<'a> {
+    pub fn new(count: u32, creator: usize) -> Self {
+        Semaphore { count, waiters: WaitQueue::new(), creator }
+    }
+}
+
+/// Mutex that processes lock through syscalls.
+/// While processes wait for it, the owner runs at the highest of their priorities.
+pub struct Mutex<'a> {
+    // Id of the process holding the lock
+    pub owner: Option<usize>,
+    pub waiters: WaitQueue<'a>,
+    // The mutex is deleted when this process stops
+    pub creator: usize,
+}
+
+impl<'a> Mutex<'a> {
+    pub fn new(creator: usize) -> Self {
+        Mutex { owner: None, waiters: WaitQueue::new(), creator }
+    }
+}
+
+/// 32 flags that processes wait on until any or all bits of a mask are set.
+pub struct EventFlags<'a> {
+    pub flags: u32,
+    pub waiters: WaitQueue<'a>,
+    // The group is deleted when this process stops. None for the kernel's own groups.
+    pub creator: Option<usize>,
+}
+
+impl<'a> EventFlags<'a> {
+    pub fn new(creator: Option<usize>) -> Self {
+        EventFlags { flags: 0, waiters: WaitQueue::new(), creator }
+    }
+}
+
+/// Set `bits` in the event group `handle` from an interrupt handler.
+/// The kernel wakes the waiters the next time it runs.
+pub fn set_flags_from_isr(handle: usize, bits: u32) {
+    if let Some(flags) = ISR_FLAGS.get(handle) {
+        flags.fetch_or(bits, Ordering::Relaxed);
+        scheduler::request_switch(scheduler::SWITCH_EVENT);
+    }
+}
+
+/// Take the flags that interrupt handlers have set in `handle`.
+pub fn take_isr_flags(handle: usize) -> u32 {
+    ISR_FLAGS[handle].swap(0, Ordering::Relaxed)
+}
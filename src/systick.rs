@@ -1,15 +1,50 @@
 use cortex_m_semihosting::hprintln;
 use core::ptr::write_volatile;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 const CSR_ADDR: usize = 0xE000_E010;
 const RVR_ADDR: usize = 0xE000_E014;
 const CVR_ADDR: usize = 0xE000_E018;
 
+// ENABLE | TICKINT | CLKSOURCE(processor clock)
+const CSR_ENABLE_PROCESSOR_CLOCK: u32 = 0x7;
+
+/// Core clock set up by the bootloader. The kernel does not configure the
+/// clocks itself, so ticks only last 1/TICK_HZ s when the bootloader has
+/// left the core running at 120 MHz.
+const CPU_FREQUENCY: u32 = 120_000_000;
+pub const TICK_HZ: u32 = 1000;
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+
 pub fn init() {
     hprintln!("Systick init").unwrap();
     unsafe {
         write_volatile(CVR_ADDR as *mut u32, 0);
-        write_volatile(RVR_ADDR as *mut u32, 1 << 23);
-        write_volatile(CSR_ADDR as *mut u32, 0x3);
+        write_volatile(RVR_ADDR as *mut u32, CPU_FREQUENCY / TICK_HZ - 1);
+        write_volatile(CSR_ADDR as *mut u32, CSR_ENABLE_PROCESSOR_CLOCK);
     }
-}
\ No newline at end of file
+}
+
+/// Count one tick. Called from the SysTick handler.
+pub fn tick() -> u32 {
+    TICKS.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+}
+
+/// Ticks since `init`. Wraps around after u32::MAX ticks.
+pub fn now() -> u32 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Furthest a deadline can be ahead. `is_reached` takes anything further for the past.
+pub const MAX_TICKS_AHEAD: u32 = userland::abi::MAX_TIMEOUT;
+
+/// Ticks that last at least `ms`, clamped to `MAX_TICKS_AHEAD`.
+pub fn ms_to_ticks(ms: u32) -> u32 {
+    ((ms as u64 * TICK_HZ as u64 + 999) / 1000).min(MAX_TICKS_AHEAD as u64) as u32
+}
+
+/// Whether `deadline` is not in the future, treating the counter as wrapping.
+pub fn is_reached(deadline: u32, now: u32) -> bool {
+    (now.wrapping_sub(deadline) as i32) >= 0
+}
@@ -0,0 +1,112 @@
+use crate::linked_list::{LinkedList, ListItem};
+use crate::systick;
+
+/// Deadline of a process that waits with a timeout.
+pub struct Timer {
+    pub deadline: u32,
+    pub process: usize,
+}
+
+/// Timers sorted by deadline, the earliest first. The kernel publishes the
+/// first deadline for SysTick to check on every tick, and expires the timers
+/// once SysTick reports that it is reached.
+pub struct TimerList<'a> {
+    list: LinkedList<'a, Timer>,
+}
+
+// Whether `a` comes before `b`. Deadlines are less than half the counter range ahead.
+fn is_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+impl<'a> TimerList<'a> {
+    pub fn new() -> Self {
+        TimerList { list: LinkedList::new() }
+    }
+
+    /// Start `timer`, which must not be in the list yet. Timers with the
+    /// same deadline expire in the order they were added.
+    pub fn add(&mut self, timer: &'a mut ListItem<'a, Timer>, deadline: u32) {
+        timer.deadline = deadline;
+        self.list.insert_before(timer, |t| is_before(deadline, t.deadline));
+    }
+
+    /// Remove the timer of `process`. Returns false when it has none.
+    pub fn cancel(&mut self, process: usize) -> bool {
+        self.list.remove_first(|t| t.process == process).is_some()
+    }
+
+    /// Remove the first timer if its deadline is reached and return its process.
+    pub fn expire(&mut self, now: u32) -> Option<usize> {
+        if !systick::is_reached(self.next_deadline()?, now) {
+            return None;
+        }
+        self.list.pop().map(|t| t.process)
+    }
+
+    pub fn next_deadline(&self) -> Option<u32> {
+        self.list.head().map(|t| t.deadline)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Timer, TimerList};
+    use crate::linked_list::ListItem;
+
+    fn timer<'a>(process: usize) -> ListItem<'a, Timer> {
+        ListItem::new(Timer { deadline: 0, process })
+    }
+
+    #[test]
+    fn test_timer_order() {
+        let (mut timer1, mut timer2, mut timer3, mut timer4) = (timer(1), timer(2), timer(3), timer(4));
+        let mut timers = TimerList::new();
+        timers.add(&mut timer1, 30);
+        timers.add(&mut timer2, 10);
+        timers.add(&mut timer3, 20);
+        timers.add(&mut timer4, 10);
+        assert_eq!(Some(10), timers.next_deadline());
+
+        assert_eq!(None, timers.expire(9));
+        assert_eq!(Some(2), timers.expire(10));
+        assert_eq!(Some(4), timers.expire(10));
+        assert_eq!(None, timers.expire(10));
+        assert!(timers.cancel(3));
+        assert!(!timers.cancel(3));
+        assert_eq!(Some(1), timers.expire(100));
+        assert_eq!(None, timers.next_deadline());
+    }
+
+    #[test]
+    fn test_timer_order_across_wrap() {
+        let (mut timer1, mut timer2, mut timer3) = (timer(1), timer(2), timer(3));
+        let mut timers = TimerList::new();
+        // Added at u32::MAX - 10: 5 lies after the wrap, so it comes last
+        timers.add(&mut timer1, 5);
+        timers.add(&mut timer2, u32::MAX - 2);
+        timers.add(&mut timer3, u32::MAX);
+        assert_eq!(Some(u32::MAX - 2), timers.next_deadline());
+
+        assert_eq!(None, timers.expire(u32::MAX - 3));
+        assert_eq!(Some(2), timers.expire(u32::MAX - 2));
+        assert_eq!(None, timers.expire(u32::MAX - 1));
+        assert_eq!(Some(3), timers.expire(0));
+        assert_eq!(None, timers.expire(4));
+        assert_eq!(Some(1), timers.expire(5));
+    }
+
+    #[test]
+    fn test_timer_reuse() {
+        let mut timer1 = timer(1);
+        let timer1 = &mut timer1 as *mut ListItem<Timer>;
+        let mut timers = TimerList::new();
+        // A process starts its timer again after it expires or is cancelled
+        timers.add(unsafe { &mut *timer1 }, 10);
+        assert_eq!(Some(1), timers.expire(10));
+        timers.add(unsafe { &mut *timer1 }, 20);
+        assert!(timers.cancel(1));
+        timers.add(unsafe { &mut *timer1 }, 30);
+        assert_eq!(Some(30), timers.next_deadline());
+    }
+}
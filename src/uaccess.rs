@@ -0,0 +1,31 @@
+use core::ptr;
+use userland::abi::SyscallError;
+use crate::mpu::Access;
+use crate::process::Process;
+
+// Kernel buffer size for syscalls that stream user memory
+pub const CHUNK_SIZE: usize = 64;
+
+/// Copy `dst.len()` bytes from the address `src` of `process`.
+/// Fails when any of them is outside the memory the process may read.
+pub fn copy_from_user(process: &Process, dst: &mut [u8], src: usize) -> Result<(), SyscallError> {
+    if !process.can_access(src, dst.len(), Access::ReadOnly) {
+        return Err(SyscallError::BadAddress);
+    }
+    unsafe {
+        ptr::copy_nonoverlapping(src as *const u8, dst.as_mut_ptr(), dst.len());
+    }
+    Ok(())
+}
+
+/// Copy `src` to the address `dst` of `process`.
+/// Fails when any byte is outside the memory the process may write.
+pub fn copy_to_user(process: &Process, dst: usize, src: &[u8]) -> Result<(), SyscallError> {
+    if !process.can_access(dst, src.len(), Access::ReadWrite) {
+        return Err(SyscallError::BadAddress);
+    }
+    unsafe {
+        ptr::copy_nonoverlapping(src.as_ptr(), dst as *mut u8, src.len());
+    }
+    Ok(())
+}
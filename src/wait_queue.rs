@@ -0,0 +1,45 @@
+use crate::linked_list::{Iter, IterMut, LinkedList, ListItem};
+use crate::process::{Process, ProcessState};
+
+/// Processes blocked on the same event, in the order they started waiting.
+pub struct WaitQueue<'a> {
+    list: LinkedList<'a, Process<'a>>,
+}
+
+impl<'a> WaitQueue<'a> {
+    pub fn new() -> Self {
+        WaitQueue {
+            list: LinkedList::new(),
+        }
+    }
+
+    pub fn block(&mut self, item: &'a mut ListItem<'a, Process<'a>>) {
+        item.set_state(ProcessState::Blocked);
+        self.list.push(item);
+    }
+
+    /// Remove the process that has waited longest.
+    pub fn wake_one(&mut self) -> Option<&'a mut ListItem<'a, Process<'a>>> {
+        self.list.pop()
+    }
+
+    /// Remove the waiting process with the highest priority.
+    /// Among equal priorities, the one that has waited longest.
+    pub fn wake_highest(&mut self) -> Option<&'a mut ListItem<'a, Process<'a>>> {
+        let priority = self.list.iter().map(|p| p.priority()).min()?;
+        self.list.remove_first(|p| p.priority() == priority)
+    }
+
+    /// Remove the first waiting process that matches `f`.
+    pub fn wake_first<F: FnMut(&Process<'a>) -> bool>(&mut self, f: F) -> Option<&'a mut ListItem<'a, Process<'a>>> {
+        self.list.remove_first(f)
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'a, Process<'a>> {
+        self.list.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, 'a, Process<'a>> {
+        self.list.iter_mut()
+    }
+}
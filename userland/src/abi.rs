@@ -0,0 +1,296 @@
+//! The syscall ABI shared by the kernel and apps.
+
+/// Entry point of an app. It receives its initial argument in r0, and
+/// returning from it exits the app with the returned code.
+pub type Entry = extern "C" fn(usize) -> u32;
+
+/// Syscall numbers, passed in r0. The arguments go in r1, r2, r3 and r12.
+///
+/// The timeout of a blocking syscall is in ticks. It fails with `TimedOut`
+/// once they have passed, `WAIT_FOREVER` waits without a limit and 0 fails
+/// at once instead of blocking. Timeouts above `MAX_TIMEOUT` other than
+/// `WAIT_FOREVER` fail with `InvalidArgument`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum Syscall {
+    Yield = 0,
+    /// r1: 0 turns the LED off, anything else on
+    SetLed = 1,
+    /// Returns 1 while button 1 is pushed
+    GetButton = 2,
+    /// r1: new priority of the caller
+    SetPriority = 3,
+    /// r1: milliseconds to sleep, cut to `MAX_TIMEOUT` ticks
+    Sleep = 4,
+    /// Returns the ticks since boot
+    GetTime = 5,
+    /// r1: exit code. Does not return.
+    Exit = 6,
+    /// r1: entry, r2: argument, r3: priority, r12: stack size. Returns the new id.
+    Spawn = 7,
+    /// r1: process id or `CURRENT_PROCESS`. Returns the deepest stack usage of the process in bytes
+    StackUsage = 8,
+    /// r1: file descriptor, r2: buffer, r3: length. Returns the bytes written.
+    Write = 9,
+    /// r1: port, r2: pin. Fails with `Busy` while another process owns it.
+    GpioClaim = 10,
+    /// r1: port, r2: pin
+    GpioRelease = 11,
+    /// r1: port, r2: pin, r3: `PinMode`
+    GpioConfigure = 12,
+    /// r1: port, r2: pin, r3: 0 for low, anything else for high
+    GpioWrite = 13,
+    /// r1: port, r2: pin. Returns 1 while the input is high.
+    GpioRead = 14,
+    /// r1: port, r2: pin
+    GpioToggle = 15,
+    /// r1: mask of buttons, bit 0 for button 1, r2: timeout. Blocks until
+    /// one of them has an event and returns it as `ButtonEvent::to_u32`.
+    WaitButtonEvent = 16,
+    /// r1: initial count. Returns a handle. Semaphore, mutex and event group
+    /// handles are small integers shared by all processes: any process that
+    /// knows one can use it, so apps must not rely on them to isolate each other.
+    /// They are deleted when the process that created them stops, and waits
+    /// on them then fail with `InvalidArgument`.
+    SemCreate = 17,
+    /// r1: handle, r2: timeout. Blocks while the count is 0.
+    SemWait = 18,
+    /// r1: handle
+    SemPost = 19,
+    /// Returns a handle
+    MutexCreate = 20,
+    /// r1: handle, r2: timeout. Blocks while another process holds the mutex.
+    MutexLock = 21,
+    /// r1: handle
+    MutexUnlock = 22,
+    /// r1: destination id, r2: message of `MESSAGE_SIZE` bytes, r3: timeout.
+    /// Blocks while the mailbox of the destination is full.
+    Send = 23,
+    /// r1: buffer of `MESSAGE_SIZE` bytes, r2: sender id or `ANY_SENDER`,
+    /// r3: timeout. Blocks until a message arrives and returns the id of its sender.
+    Receive = 24,
+    /// r1: destination id, r2: message, r3: buffer for the reply, r12: timeout.
+    /// Sends the message, then receives from the destination.
+    Call = 25,
+    /// r1: grantee id, r2: buffer on the stack of the caller, r3: size,
+    /// r12: `GrantAccess`. The buffer must be a power of two of at least
+    /// 32 bytes, aligned to its size. Returns a handle.
+    Grant = 26,
+    /// r1: handle
+    Revoke = 27,
+    /// Returns a handle
+    EventCreate = 28,
+    /// r1: handle, r2: flags to set
+    EventSet = 29,
+    /// r1: handle, r2: flags to clear. Returns the flags before clearing.
+    EventClear = 30,
+    /// r1: handle, r2: mask, r3: `EVENT_WAIT_ALL` and `EVENT_CLEAR` options,
+    /// r12: timeout. Blocks until any bit of the
+    /// mask is set, or all with `EVENT_WAIT_ALL`, and returns the flags.
+    EventWait = 31,
+    /// r1: process id, r2: timeout. Blocks until the process finishes for
+    /// good and returns its exit code. Fails with `NoSuchProcess` for an id
+    /// that never existed or finished too long ago.
+    Join = 32,
+}
+
+impl Syscall {
+    pub const COUNT: usize = 33;
+
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Syscall::Yield),
+            1 => Some(Syscall::SetLed),
+            2 => Some(Syscall::GetButton),
+            3 => Some(Syscall::SetPriority),
+            4 => Some(Syscall::Sleep),
+            5 => Some(Syscall::GetTime),
+            6 => Some(Syscall::Exit),
+            7 => Some(Syscall::Spawn),
+            8 => Some(Syscall::StackUsage),
+            9 => Some(Syscall::Write),
+            10 => Some(Syscall::GpioClaim),
+            11 => Some(Syscall::GpioRelease),
+            12 => Some(Syscall::GpioConfigure),
+            13 => Some(Syscall::GpioWrite),
+            14 => Some(Syscall::GpioRead),
+            15 => Some(Syscall::GpioToggle),
+            16 => Some(Syscall::WaitButtonEvent),
+            17 => Some(Syscall::SemCreate),
+            18 => Some(Syscall::SemWait),
+            19 => Some(Syscall::SemPost),
+            20 => Some(Syscall::MutexCreate),
+            21 => Some(Syscall::MutexLock),
+            22 => Some(Syscall::MutexUnlock),
+            23 => Some(Syscall::Send),
+            24 => Some(Syscall::Receive),
+            25 => Some(Syscall::Call),
+            26 => Some(Syscall::Grant),
+            27 => Some(Syscall::Revoke),
+            28 => Some(Syscall::EventCreate),
+            29 => Some(Syscall::EventSet),
+            30 => Some(Syscall::EventClear),
+            31 => Some(Syscall::EventWait),
+            32 => Some(Syscall::Join),
+            _ => None,
+        }
+    }
+}
+
+/// Error codes returned in r0. 0 means success, with the return value in r1.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum SyscallError {
+    NoSuchSyscall = 1,
+    InvalidArgument = 2,
+    NoMemory = 3,
+    /// A buffer is outside the memory of the caller
+    BadAddress = 4,
+    BadFileDescriptor = 5,
+    /// Another process owns the resource
+    Busy = 6,
+    /// The caller does not own the resource
+    PermissionDenied = 7,
+    /// The caller would wait for itself
+    Deadlock = 8,
+    NoSuchProcess = 9,
+    /// The timeout expired before the wait was satisfied
+    TimedOut = 10,
+}
+
+impl SyscallError {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            1 => Some(SyscallError::NoSuchSyscall),
+            2 => Some(SyscallError::InvalidArgument),
+            3 => Some(SyscallError::NoMemory),
+            4 => Some(SyscallError::BadAddress),
+            5 => Some(SyscallError::BadFileDescriptor),
+            6 => Some(SyscallError::Busy),
+            7 => Some(SyscallError::PermissionDenied),
+            8 => Some(SyscallError::Deadlock),
+            9 => Some(SyscallError::NoSuchProcess),
+            10 => Some(SyscallError::TimedOut),
+            _ => None,
+        }
+    }
+}
+
+pub type SyscallResult = Result<u32, SyscallError>;
+
+/// Split a result into the values of r0 and r1.
+pub fn encode_result(result: SyscallResult) -> (u32, u32) {
+    match result {
+        Ok(value) => (0, value),
+        Err(error) => (error as u32, 0),
+    }
+}
+
+/// Rebuild a result from the values of r0 and r1.
+pub fn decode_result(status: u32, value: u32) -> SyscallResult {
+    match status {
+        0 => Ok(value),
+        _ => Err(SyscallError::from_u32(status).unwrap_or(SyscallError::NoSuchSyscall)),
+    }
+}
+
+/// File descriptors of the console.
+pub const STDOUT: u32 = 1;
+pub const STDERR: u32 = 2;
+
+/// GPIO ports, as the GPIO syscalls number them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum Port {
+    A = 0,
+    B = 1,
+    C = 2,
+    D = 3,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum PinMode {
+    Output = 0,
+    Input = 1,
+    InputPullUp = 2,
+    InputPullDown = 3,
+}
+
+impl PinMode {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(PinMode::Output),
+            1 => Some(PinMode::Input),
+            2 => Some(PinMode::InputPullUp),
+            3 => Some(PinMode::InputPullDown),
+            _ => None,
+        }
+    }
+}
+
+pub const NUM_BUTTONS: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ButtonEventKind {
+    Press = 0,
+    Release = 1,
+    /// The button has been held down for a second
+    LongPress = 2,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ButtonEvent {
+    /// 0 for button 1
+    pub button: u32,
+    pub kind: ButtonEventKind,
+}
+
+impl ButtonEvent {
+    pub fn to_u32(&self) -> u32 {
+        self.button | (self.kind as u32) << 8
+    }
+
+    pub fn from_u32(value: u32) -> Option<Self> {
+        let kind = match value >> 8 {
+            0 => ButtonEventKind::Press,
+            1 => ButtonEventKind::Release,
+            2 => ButtonEventKind::LongPress,
+            _ => return None,
+        };
+        Some(ButtonEvent { button: value & 0xFF, kind })
+    }
+}
+
+/// Size of an IPC message in bytes.
+pub const MESSAGE_SIZE: usize = 16;
+/// Receive from whichever process sends first.
+pub const ANY_SENDER: u32 = u32::MAX;
+/// Process id that stands for the caller.
+pub const CURRENT_PROCESS: u32 = u32::MAX;
+
+/// What the grantee of a buffer may do with it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum GrantAccess {
+    ReadOnly = 0,
+    ReadWrite = 1,
+}
+
+/// Timeout of a wait that only ends when it is satisfied.
+pub const WAIT_FOREVER: u32 = u32::MAX;
+/// Longest timeout in ticks. The kernel compares deadlines with the wrapping
+/// tick counter, so they must be less than half its range ahead.
+pub const MAX_TIMEOUT: u32 = i32::MAX as u32;
+
+/// Wait until all bits of the mask are set instead of any.
+pub const EVENT_WAIT_ALL: u32 = 1 << 0;
+/// Clear the bits of the mask when the wait is satisfied.
+pub const EVENT_CLEAR: u32 = 1 << 1;
+/// Event group that the kernel creates at boot. Bit 0 is set when button 1 is pressed.
+/// Apps can only wait on it: setting or clearing it fails with `PermissionDenied`.
+pub const BUTTON_EVENT_FLAGS: u32 = 0;
+
+/// Exit code of an app that panicked.
+pub const EXIT_PANIC: u32 = 101;
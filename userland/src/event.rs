@@ -0,0 +1,47 @@
+//! Event groups: 32 flags that apps and interrupt handlers set and apps wait on.
+
+use crate::abi::{Syscall, SyscallError, BUTTON_EVENT_FLAGS, EVENT_CLEAR, EVENT_WAIT_ALL};
+use crate::syscall::syscall;
+
+pub struct EventFlags {
+    handle: u32,
+}
+
+impl EventFlags {
+    pub fn new() -> Result<Self, SyscallError> {
+        let handle = syscall(Syscall::EventCreate, [0; 4])?;
+        Ok(EventFlags { handle })
+    }
+
+    /// The group the kernel sets a bit in when a button is pressed, bit 0 for button 1.
+    /// Waits on it should clear the bits, since `clear` is not allowed.
+    pub fn buttons() -> Self {
+        EventFlags { handle: BUTTON_EVENT_FLAGS }
+    }
+
+    pub fn set(&self, bits: u32) -> Result<(), SyscallError> {
+        syscall(Syscall::EventSet, [self.handle, bits, 0, 0]).map(|_| ())
+    }
+
+    /// Returns the flags before clearing.
+    pub fn clear(&self, bits: u32) -> Result<u32, SyscallError> {
+        syscall(Syscall::EventClear, [self.handle, bits, 0, 0])
+    }
+
+    /// Block until any bit of `mask` is set, then clear the bits of `mask`
+    /// and return the flags. Fails with `TimedOut` after `timeout` ticks.
+    pub fn wait_any(&self, mask: u32, timeout: u32) -> Result<u32, SyscallError> {
+        self.wait(mask, EVENT_CLEAR, timeout)
+    }
+
+    /// Block until all bits of `mask` are set, then clear them and return the flags.
+    pub fn wait_all(&self, mask: u32, timeout: u32) -> Result<u32, SyscallError> {
+        self.wait(mask, EVENT_WAIT_ALL | EVENT_CLEAR, timeout)
+    }
+
+    /// Wait with the `EVENT_WAIT_ALL` and `EVENT_CLEAR` `options`.
+    /// `WAIT_FOREVER` disables the timeout and 0 only polls.
+    pub fn wait(&self, mask: u32, options: u32, timeout: u32) -> Result<u32, SyscallError> {
+        syscall(Syscall::EventWait, [self.handle, mask, options, timeout])
+    }
+}
@@ -0,0 +1,44 @@
+//! Pins that the app claims from the kernel.
+
+use crate::abi::{PinMode, Port, Syscall, SyscallError};
+use crate::syscall::syscall;
+
+/// A pin owned by this app. Dropping it gives it back to the kernel.
+pub struct Pin {
+    port: Port,
+    n: u32,
+}
+
+impl Pin {
+    /// Claim pin `n` of `port`. Fails with `Busy` while another app or a kernel driver owns it.
+    pub fn claim(port: Port, n: u32) -> Result<Self, SyscallError> {
+        syscall(Syscall::GpioClaim, [port as u32, n, 0, 0])?;
+        Ok(Pin { port, n })
+    }
+
+    pub fn configure(&self, mode: PinMode) -> Result<(), SyscallError> {
+        self.call(Syscall::GpioConfigure, mode as u32).map(|_| ())
+    }
+
+    pub fn write(&self, high: bool) -> Result<(), SyscallError> {
+        self.call(Syscall::GpioWrite, high as u32).map(|_| ())
+    }
+
+    pub fn read(&self) -> Result<bool, SyscallError> {
+        self.call(Syscall::GpioRead, 0).map(|value| value != 0)
+    }
+
+    pub fn toggle(&self) -> Result<(), SyscallError> {
+        self.call(Syscall::GpioToggle, 0).map(|_| ())
+    }
+
+    fn call(&self, syscall_id: Syscall, arg: u32) -> Result<u32, SyscallError> {
+        syscall(syscall_id, [self.port as u32, self.n, arg, 0])
+    }
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        let _ = self.call(Syscall::GpioRelease, 0);
+    }
+}
@@ -0,0 +1,39 @@
+//! Sharing a buffer with another app without copying it through messages.
+
+use core::marker::PhantomData;
+use crate::abi::{GrantAccess, Syscall, SyscallError};
+use crate::syscall::syscall;
+
+/// Access that another app has to a buffer on the stack of this app.
+/// The buffer stays borrowed until the grant is dropped, which revokes it.
+pub struct Grant<'b> {
+    handle: u32,
+    addr: usize,
+    len: usize,
+    marker: PhantomData<&'b mut [u8]>,
+}
+
+impl<'b> Grant<'b> {
+    /// Let app `dest` access `buf`. Its length must be a power of two of at
+    /// least 32 bytes and it must be aligned to its length. Send `addr` and
+    /// `size` to the other app so it can find the buffer.
+    pub fn new(dest: usize, buf: &'b mut [u8], access: GrantAccess) -> Result<Self, SyscallError> {
+        let (addr, len) = (buf.as_mut_ptr() as usize, buf.len());
+        let handle = syscall(Syscall::Grant, [dest as u32, addr as u32, len as u32, access as u32])?;
+        Ok(Grant { handle, addr, len, marker: PhantomData })
+    }
+
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    pub fn size(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for Grant<'_> {
+    fn drop(&mut self) {
+        let _ = syscall(Syscall::Revoke, [self.handle, 0, 0, 0]);
+    }
+}
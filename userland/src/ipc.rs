@@ -0,0 +1,46 @@
+//! Message passing between apps. Every app has a mailbox of a few messages.
+//!
+//! The `_timeout` variants fail with `TimedOut` after `timeout` ticks.
+
+use crate::abi::{Syscall, SyscallError, ANY_SENDER, MESSAGE_SIZE, WAIT_FOREVER};
+use crate::syscall::syscall;
+
+pub type Message = [u8; MESSAGE_SIZE];
+
+/// Put `message` in the mailbox of `dest`, blocking while it is full.
+pub fn send(dest: usize, message: &Message) -> Result<(), SyscallError> {
+    send_timeout(dest, message, WAIT_FOREVER)
+}
+
+pub fn send_timeout(dest: usize, message: &Message, timeout: u32) -> Result<(), SyscallError> {
+    syscall(Syscall::Send, [dest as u32, message.as_ptr() as u32, timeout, 0]).map(|_| ())
+}
+
+/// Wait for a message from any app. Returns the id of the sender.
+pub fn receive(buffer: &mut Message) -> Result<usize, SyscallError> {
+    receive_timeout(buffer, WAIT_FOREVER)
+}
+
+pub fn receive_timeout(buffer: &mut Message, timeout: u32) -> Result<usize, SyscallError> {
+    syscall(Syscall::Receive, [buffer.as_mut_ptr() as u32, ANY_SENDER, timeout, 0]).map(|sender| sender as usize)
+}
+
+/// Wait for a message from `sender`. Messages from other apps stay in the mailbox.
+pub fn receive_from(sender: usize, buffer: &mut Message) -> Result<(), SyscallError> {
+    receive_from_timeout(sender, buffer, WAIT_FOREVER)
+}
+
+pub fn receive_from_timeout(sender: usize, buffer: &mut Message, timeout: u32) -> Result<(), SyscallError> {
+    syscall(Syscall::Receive, [buffer.as_mut_ptr() as u32, sender as u32, timeout, 0]).map(|_| ())
+}
+
+/// Send `message` to `dest` and wait for it to send a reply back.
+pub fn call(dest: usize, message: &Message, reply: &mut Message) -> Result<(), SyscallError> {
+    call_timeout(dest, message, reply, WAIT_FOREVER)
+}
+
+/// Like `call`, with one timeout for sending and waiting for the reply.
+pub fn call_timeout(dest: usize, message: &Message, reply: &mut Message, timeout: u32) -> Result<(), SyscallError> {
+    let args = [dest as u32, message.as_ptr() as u32, reply.as_mut_ptr() as u32, timeout];
+    syscall(Syscall::Call, args).map(|_| ())
+}
@@ -0,0 +1,42 @@
+//! Library for apps running on bookos.
+//!
+//! Apps only talk to the kernel through the syscalls in `syscall`.
+
+#![no_std]
+
+pub mod abi;
+pub mod event;
+pub mod gpio;
+pub mod grant;
+pub mod io;
+pub mod ipc;
+pub mod sync;
+pub mod syscall;
+
+pub use syscall::*;
+
+/// Define the entry point of an app from a `fn(usize) -> u32`.
+/// The kernel passes the argument it was started with, and returning
+/// exits the app with the returned code.
+///
+/// `entry!(main)` exports the entry as `_start`. `entry!(name, main)`
+/// picks the symbol name, for several apps linked into one image.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        $crate::entry!(_start, $main);
+    };
+    ($name:ident, $main:path) => {
+        #[no_mangle]
+        pub extern "C" fn $name(arg: usize) -> u32 {
+            let main: fn(usize) -> u32 = $main;
+            main(arg)
+        }
+    };
+}
+
+#[cfg(feature = "panic-handler")]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo<'_>) -> ! {
+    syscall::exit(abi::EXIT_PANIC)
+}
@@ -0,0 +1,66 @@
+//! Semaphores and mutexes that block in the kernel instead of spinning.
+//!
+//! Their handles are shared system-wide. Any app can post to a semaphore it
+//! did not create, but only the app holding a mutex can unlock it. The kernel
+//! deletes them when the app that created them exits or is restarted.
+
+use crate::abi::{Syscall, SyscallError, WAIT_FOREVER};
+use crate::syscall::syscall;
+
+pub struct Semaphore {
+    handle: u32,
+}
+
+impl Semaphore {
+    pub fn new(count: u32) -> Result<Self, SyscallError> {
+        let handle = syscall(Syscall::SemCreate, [count, 0, 0, 0])?;
+        Ok(Semaphore { handle })
+    }
+
+    /// Take one unit, blocking until there is one.
+    pub fn wait(&self) -> Result<(), SyscallError> {
+        self.wait_timeout(WAIT_FOREVER)
+    }
+
+    /// Take one unit, failing with `TimedOut` when none comes within `timeout` ticks.
+    pub fn wait_timeout(&self, timeout: u32) -> Result<(), SyscallError> {
+        syscall(Syscall::SemWait, [self.handle, timeout, 0, 0]).map(|_| ())
+    }
+
+    /// Give back one unit, waking a waiter if there is one.
+    pub fn post(&self) -> Result<(), SyscallError> {
+        syscall(Syscall::SemPost, [self.handle, 0, 0, 0]).map(|_| ())
+    }
+}
+
+pub struct Mutex {
+    handle: u32,
+}
+
+impl Mutex {
+    pub fn new() -> Result<Self, SyscallError> {
+        let handle = syscall(Syscall::MutexCreate, [0; 4])?;
+        Ok(Mutex { handle })
+    }
+
+    /// Block until the mutex is free. It is unlocked when the guard is dropped.
+    pub fn lock(&self) -> Result<MutexGuard<'_>, SyscallError> {
+        self.lock_timeout(WAIT_FOREVER)
+    }
+
+    /// Like `lock`, but fails with `TimedOut` when the mutex stays taken for `timeout` ticks.
+    pub fn lock_timeout(&self, timeout: u32) -> Result<MutexGuard<'_>, SyscallError> {
+        syscall(Syscall::MutexLock, [self.handle, timeout, 0, 0])?;
+        Ok(MutexGuard { mutex: self })
+    }
+}
+
+pub struct MutexGuard<'m> {
+    mutex: &'m Mutex,
+}
+
+impl Drop for MutexGuard<'_> {
+    fn drop(&mut self) {
+        let _ = syscall(Syscall::MutexUnlock, [self.mutex.handle, 0, 0, 0]);
+    }
+}
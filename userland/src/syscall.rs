@@ -0,0 +1,111 @@
+//! Safe wrappers for every syscall.
+
+#[cfg(target_arch = "arm")]
+use core::arch::asm;
+#[cfg(target_arch = "arm")]
+use crate::abi::decode_result;
+use crate::abi::{ButtonEvent, Entry, Syscall, SyscallError, SyscallResult, CURRENT_PROCESS, WAIT_FOREVER};
+
+#[cfg(target_arch = "arm")]
+pub(crate) fn syscall(syscall: Syscall, args: [u32; 4]) -> SyscallResult {
+    let status: u32;
+    let value: u32;
+    unsafe {
+        asm!(
+            "svc 0",
+            inout("r0") syscall as u32 => status,
+            inout("r1") args[0] => value,
+            in("r2") args[1], in("r3") args[2], in("r12") args[3],
+        );
+    }
+    decode_result(status, value)
+}
+
+// The kernel's unit tests build this crate for the host, where there is no kernel to call
+#[cfg(not(target_arch = "arm"))]
+pub(crate) fn syscall(_syscall: Syscall, _args: [u32; 4]) -> SyscallResult {
+    unimplemented!()
+}
+
+/// Give the CPU to the next ready app of the same priority.
+pub fn yield_now() {
+    syscall(Syscall::Yield, [0; 4]).unwrap();
+}
+
+pub fn set_led(value: bool) {
+    syscall(Syscall::SetLed, [value as u32, 0, 0, 0]).unwrap();
+}
+
+/// Whether button 1 is pushed.
+pub fn get_button() -> bool {
+    syscall(Syscall::GetButton, [0; 4]).unwrap() > 0
+}
+
+/// Wait for a press, release or long press of the buttons in `mask`, bit 0 for button 1.
+pub fn wait_button_event(mask: u32) -> Result<ButtonEvent, SyscallError> {
+    wait_button_event_timeout(mask, WAIT_FOREVER)
+}
+
+/// Like `wait_button_event`, but fails with `TimedOut` after `timeout` ticks.
+pub fn wait_button_event_timeout(mask: u32, timeout: u32) -> Result<ButtonEvent, SyscallError> {
+    let event = syscall(Syscall::WaitButtonEvent, [mask, timeout, 0, 0])?;
+    ButtonEvent::from_u32(event).ok_or(SyscallError::InvalidArgument)
+}
+
+/// Change the priority of the calling app. 0 is the highest.
+pub fn set_priority(priority: usize) -> Result<(), SyscallError> {
+    syscall(Syscall::SetPriority, [priority as u32, 0, 0, 0]).map(|_| ())
+}
+
+pub fn sleep(ms: u32) {
+    syscall(Syscall::Sleep, [ms, 0, 0, 0]).unwrap();
+}
+
+/// Ticks since boot.
+pub fn get_time() -> u32 {
+    syscall(Syscall::GetTime, [0; 4]).unwrap()
+}
+
+#[cfg(target_arch = "arm")]
+pub fn exit(code: u32) -> ! {
+    unsafe {
+        asm!("svc 0", in("r0") Syscall::Exit as u32, in("r1") code, options(noreturn));
+    }
+}
+
+#[cfg(not(target_arch = "arm"))]
+pub fn exit(_code: u32) -> ! {
+    unimplemented!()
+}
+
+/// Start a new app running `entry(arg)` on a stack of `stack_size` bytes. Returns its id.
+pub fn spawn(entry: Entry, arg: usize, priority: usize, stack_size: usize) -> Result<usize, SyscallError> {
+    let args = [entry as u32, arg as u32, priority as u32, stack_size as u32];
+    syscall(Syscall::Spawn, args).map(|id| id as usize)
+}
+
+/// Wait for the app `id` to finish and return its exit code.
+/// An app that restarts has not finished yet.
+pub fn join(id: usize) -> Result<u32, SyscallError> {
+    join_timeout(id, WAIT_FOREVER)
+}
+
+/// Like `join`, but fails with `TimedOut` after `timeout` ticks.
+pub fn join_timeout(id: usize, timeout: u32) -> Result<u32, SyscallError> {
+    syscall(Syscall::Join, [id as u32, timeout, 0, 0])
+}
+
+/// Write `buf` to the file descriptor `fd`. Returns the number of bytes written.
+pub fn write(fd: u32, buf: &[u8]) -> Result<usize, SyscallError> {
+    syscall(Syscall::Write, [fd, buf.as_ptr() as u32, buf.len() as u32, 0]).map(|len| len as usize)
+}
+
+/// Deepest stack usage of the calling app so far, in bytes.
+pub fn stack_usage() -> usize {
+    syscall(Syscall::StackUsage, [CURRENT_PROCESS, 0, 0, 0]).unwrap() as usize
+}
+
+/// Deepest stack usage of the app `id` so far, in bytes.
+pub fn stack_usage_of(id: usize) -> Result<usize, SyscallError> {
+    syscall(Syscall::StackUsage, [id as u32, 0, 0, 0]).map(|usage| usage as usize)
+}